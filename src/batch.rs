@@ -0,0 +1,105 @@
+//! Reading the expressions to roll from files and stdin, one per line.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use cli::Input;
+use rcmd::{ParseError, RollError};
+
+/// An expression to roll, along with the file and line it was read from
+/// unless it was given as an argument.
+pub struct Line {
+    pub text: String,
+    pub origin: Option<(String, usize)>,
+}
+
+impl Line {
+    /// Describes an expression that failed to parse: with carets under the
+    /// offending part for an argument, or with the line and column in
+    /// compiler style for a line of a file.
+    pub fn parse_error(&self, e: &ParseError) -> String {
+        match self.origin {
+            Some((ref name, line)) => {
+                let column = self.text.get(..e.span().start).map_or(0, |s| s.chars().count());
+                format!("{}:{}:{}: {}", name, line, column + 1, e)
+            }
+            None => e.render(&self.text),
+        }
+    }
+
+    /// Describes an expression that failed to roll.
+    pub fn roll_error(&self, e: &RollError) -> String {
+        match self.origin {
+            Some((ref name, line)) => format!("{}:{}: {}: {}", name, line, self.text, e),
+            None => format!("{}: {}", self.text, e),
+        }
+    }
+}
+
+/// Reads an expression from every line of `reader` that holds one, named
+/// `name` in errors.
+///
+/// Everything after a `#` is a comment, and lines without an expression
+/// are skipped.
+pub fn read_lines<R: BufRead>(name: &str, reader: R) -> io::Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let text = line.split('#').next().unwrap_or("").trim();
+        if !text.is_empty() {
+            lines.push(Line { text: text.to_string(), origin: Some((name.to_string(), i + 1)) });
+        }
+    }
+    Ok(lines)
+}
+
+/// Gathers the expressions of every input in the order they were given,
+/// failing with a message if a file cannot be read.
+pub fn read(inputs: Vec<Input>) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    for input in inputs {
+        match input {
+            Input::Expr(text) => lines.push(Line { text, origin: None }),
+            Input::Stdin => {
+                let stdin = io::stdin();
+                let read = read_lines("<stdin>", stdin.lock());
+                lines.extend(read.map_err(|e| format!("cannot read stdin: {}", e))?);
+            }
+            Input::File(path) => {
+                let read = File::open(&path).and_then(|f| read_lines(&path, BufReader::new(f)));
+                lines.extend(read.map_err(|e| format!("cannot read {}: {}", path, e))?);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod batch_test {
+    use super::*;
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        let input = "# loot\n1d6\n\n   \n2d20kh1 + 5  # advantage\n#4d6\n  3d8 ";
+        let lines = read_lines("rolls.txt", input.as_bytes()).unwrap();
+        let texts: Vec<_> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(vec!["1d6", "2d20kh1 + 5", "3d8"], texts);
+        let numbers: Vec<_> = lines.iter().map(|line| line.origin.as_ref().unwrap().1).collect();
+        assert_eq!(vec![2, 5, 7], numbers);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let lines = read_lines("rolls.txt", "\n1d6 + 2d".as_bytes()).unwrap();
+        let e = lines[0].text.parse::<rcmd::Expr>().unwrap_err();
+        assert_eq!("rolls.txt:2:9: missing number", lines[0].parse_error(&e));
+        assert_eq!(
+            "rolls.txt:2: 1d6 + 2d: ran out of dice to roll",
+            lines[0].roll_error(&RollError::Exhausted)
+        );
+
+        let arg = Line { text: "2d".to_string(), origin: None };
+        let e = arg.text.parse::<rcmd::Expr>().unwrap_err();
+        assert_eq!("2d\n  ^ missing number", arg.parse_error(&e));
+    }
+}
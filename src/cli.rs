@@ -0,0 +1,421 @@
+//! The command line of `dice`: its options, its usage and its exit codes.
+
+use format::Format;
+use rcmd::{Order, ParseError, PoolCommand, RollError, ServerSeed, Table};
+
+pub const USAGE: &str = "\
+usage: dice [options] <expression>...
+       dice [options] - | --file <path>
+       dice [-i] [--seed <n>]
+       dice commit
+       dice verify <commitment> <server seed> <client seed> <nonce> <expression> [result]
+       dice prob [--damage <expression>] <query>...
+
+Expressions are read one per line from stdin with -, or from a file with
+--file, in the order given. Blank lines and everything after a # are
+skipped. An expression is rolled several times with 6x4d6kh3 or
+repeat(6, 4d6kh3).
+
+options:
+  --file <path>        roll the expressions in a file, one per line
+  --seed <n>           roll with a generator seeded with n instead of the os
+  --server-seed <hex>  roll provably fair results with a seed from `dice commit`
+  --client-seed <s>    the seed chosen by the player for provably fair rolls
+  --nonce <n>          the nonce of the first provably fair roll, 0 by default
+  --format <format>    print the rolls as plain text, json, csv or ndjson
+  --sort <order>       sort the rolls of a repeat by total, asc or desc
+  --aggregate          print the sum, mean, lowest and highest total after
+                       the plain rolls of a repeat
+  --dist               print the distribution of every expression instead
+  --at-least           print the chance of rolling at least every value
+  --at-most            print the chance of rolling at most every value
+  --export <format>    export the distribution as csv or json instead
+  --keep-going         carry on after an expression fails
+  -i, --interactive    roll the expression on every line typed, the default
+                       without any arguments
+  -h, --help           print this help
+  -V, --version        print the version
+
+exit status: 0 if every expression was rolled, 1 if a roll failed, 2 for
+invalid options, 3 if an expression failed to parse, 4 if it went over a
+limit, 5 if no randomness was available and 6 if a file could not be read.";
+
+/// A roll failed for another reason than the ones below.
+pub const EXIT_FAILURE: i32 = 1;
+/// The options were invalid.
+pub const EXIT_USAGE: i32 = 2;
+/// An expression failed to parse.
+pub const EXIT_PARSE: i32 = 3;
+/// An expression went over one of the `Limits`.
+pub const EXIT_LIMIT: i32 = 4;
+/// The randomness was not available or picked impossible sides.
+pub const EXIT_RNG: i32 = 5;
+/// A file of expressions could not be read.
+pub const EXIT_IO: i32 = 6;
+
+/// Returns the exit code for an expression that failed to parse.
+pub fn parse_code(e: &ParseError) -> i32 {
+    match *e {
+        ParseError::LimitExceeded { .. } => EXIT_LIMIT,
+        _ => EXIT_PARSE,
+    }
+}
+
+/// Returns the exit code for a roll that failed.
+pub fn roll_code(e: &RollError) -> i32 {
+    match *e {
+        RollError::LimitExceeded(_) => EXIT_LIMIT,
+        RollError::FaceOutOfRange { .. } | RollError::Exhausted | RollError::Diverged { .. } => {
+            EXIT_RNG
+        }
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Suggests how to write a query for `dice prob` that was written as a
+/// pool, such as `2d6>=10`: a comparison right after dice counts the dice
+/// that hit it, so comparing the total needs spaces around it.
+pub fn query_hint(arg: &str) -> Option<String> {
+    if arg.contains('f') || arg.parse::<PoolCommand>().is_err() {
+        return None;
+    }
+    let is_compare = |c: char| c == '<' || c == '>' || c == '=';
+    let end = arg.rfind(is_compare)? + 1;
+    let start = arg[..end].trim_end_matches(is_compare).len();
+    Some(format!(
+        "{} counts the dice rolling {}, write `{} {} {}` to compare the total",
+        arg.trim(),
+        arg[start..].trim(),
+        arg[..start].trim(),
+        &arg[start..end],
+        arg[end..].trim()
+    ))
+}
+
+/// How `--dist` prints distributions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Export {
+    Chart,
+    Csv,
+    Json,
+}
+
+/// Where expressions to roll come from.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Input {
+    /// An expression given as an argument.
+    Expr(String),
+    /// One expression per line of stdin, given as `-`.
+    Stdin,
+    /// One expression per line of a file, given with `--file`.
+    File(String),
+}
+
+/// The options given to `dice` when rolling, along with the inputs of the
+/// expressions.
+#[derive(Debug)]
+pub struct Options {
+    pub seed: Option<u64>,
+    pub fair: Option<(ServerSeed, String)>,
+    pub nonce: u64,
+    pub format: Format,
+    pub order: Option<Order>,
+    pub aggregate: bool,
+    pub table: Option<Table>,
+    pub export: Export,
+    pub keep_going: bool,
+    pub interactive: bool,
+    pub help: bool,
+    pub version: bool,
+    pub inputs: Vec<Input>,
+}
+
+/// Reads the options and expressions from `args`, without the program
+/// name.
+///
+/// Options may be given as `--name value` or `--name=value`, and `--`
+/// makes every argument after it an expression. Any other argument starting
+/// with `-` is an option. Without any expressions the options are those of
+/// interactive mode, as with `-i`. Fails with a diagnostic for every invalid
+/// option.
+pub fn parse<I>(args: I) -> Result<Options, Vec<String>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options {
+        seed: None,
+        fair: None,
+        nonce: 0,
+        format: Format::Plain,
+        order: None,
+        aggregate: false,
+        table: None,
+        export: Export::Chart,
+        keep_going: false,
+        interactive: false,
+        help: false,
+        version: false,
+        inputs: Vec::new(),
+    };
+    let mut server_seed = None;
+    let mut client_seed = None;
+    let mut errors = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            options.inputs.extend(args.by_ref().map(Input::Expr));
+            break;
+        }
+        if !arg.starts_with('-') {
+            options.inputs.push(Input::Expr(arg));
+            continue;
+        }
+        if !arg.starts_with("--") {
+            match arg.as_str() {
+                "-i" => options.interactive = true,
+                "-h" => options.help = true,
+                "-V" => options.version = true,
+                "-" => options.inputs.push(Input::Stdin),
+                _ => errors.push(format!("unknown option '{}'", arg)),
+            }
+            continue;
+        }
+
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let takes_value = matches!(
+            name.as_str(),
+            "--seed" | "--server-seed" | "--client-seed" | "--nonce" | "--format" | "--export"
+                | "--file" | "--sort"
+        );
+        let value = match (takes_value, inline) {
+            (true, Some(value)) => value,
+            (true, None) => match args.next() {
+                Some(value) => value,
+                None => {
+                    errors.push(format!("{} needs a value", name));
+                    continue;
+                }
+            },
+            (false, Some(_)) => {
+                errors.push(format!("{} does not take a value", name));
+                continue;
+            }
+            (false, None) => String::new(),
+        };
+
+        match name.as_str() {
+            "--seed" => match value.parse() {
+                Ok(n) => options.seed = Some(n),
+                Err(_) => errors.push(format!(
+                    "--seed needs a number between 0 and {}, not '{}'",
+                    u64::MAX,
+                    value
+                )),
+            },
+            "--server-seed" => match value.parse::<ServerSeed>() {
+                Ok(seed) => server_seed = Some(seed),
+                Err(_) => errors.push(format!(
+                    "--server-seed needs 64 hex digits, as printed by `dice commit`, not '{}'",
+                    value
+                )),
+            },
+            "--client-seed" => client_seed = Some(value),
+            "--nonce" => match value.parse() {
+                Ok(n) => options.nonce = n,
+                Err(_) => errors.push(format!(
+                    "--nonce needs a number between 0 and {}, not '{}'",
+                    u64::MAX,
+                    value
+                )),
+            },
+            "--format" => match value.as_str() {
+                "plain" => options.format = Format::Plain,
+                "json" => options.format = Format::Json,
+                "csv" => options.format = Format::Csv,
+                "ndjson" => options.format = Format::Ndjson,
+                _ => errors.push(format!(
+                    "--format needs plain, json, csv or ndjson, not '{}'",
+                    value
+                )),
+            },
+            "--sort" => match value.as_str() {
+                "asc" => options.order = Some(Order::Ascending),
+                "desc" => options.order = Some(Order::Descending),
+                _ => errors.push(format!("--sort needs asc or desc, not '{}'", value)),
+            },
+            "--aggregate" => options.aggregate = true,
+            "--dist" => options.table = options.table.or(Some(Table::Exactly)),
+            "--at-least" => options.table = Some(Table::AtLeast),
+            "--at-most" => options.table = Some(Table::AtMost),
+            "--export" => {
+                match value.as_str() {
+                    "csv" => options.export = Export::Csv,
+                    "json" => options.export = Export::Json,
+                    _ => errors.push(format!("--export needs csv or json, not '{}'", value)),
+                }
+                options.table = options.table.or(Some(Table::Exactly));
+            }
+            "--file" => options.inputs.push(Input::File(value)),
+            "--keep-going" => options.keep_going = true,
+            "--interactive" => options.interactive = true,
+            "--help" => options.help = true,
+            "--version" => options.version = true,
+            _ => errors.push(format!("unknown option '{}'", name)),
+        }
+    }
+
+    match (server_seed, client_seed) {
+        (Some(server_seed), Some(client_seed)) => options.fair = Some((server_seed, client_seed)),
+        (None, None) => {}
+        _ => errors.push("fair rolls need both --server-seed and --client-seed".to_string()),
+    }
+    if options.fair.is_some() && options.seed.is_some() {
+        errors.push("--seed cannot be combined with fair rolls".to_string());
+    }
+    // without any expressions dice is interactive, just like with -i
+    let implicit = options.inputs.is_empty() && !options.help && !options.version;
+    let implicit = implicit && !options.interactive;
+    options.interactive |= implicit;
+    let conflicts = errors.len();
+    if options.format != Format::Plain {
+        if options.table.is_some() {
+            errors.push("--format does not apply to --dist, use --export".to_string());
+        }
+        if options.interactive {
+            errors.push("--format does not apply to interactive mode".to_string());
+        }
+        if options.aggregate {
+            errors.push("--aggregate only applies to plain output".to_string());
+        }
+    }
+    if options.interactive && (options.fair.is_some() || options.table.is_some()) {
+        errors.push("interactive mode only rolls, with --seed if given".to_string());
+    }
+    if options.interactive && options.inputs.contains(&Input::Stdin) {
+        errors.push("interactive mode reads the terminal, it cannot read - as well".to_string());
+    }
+    if implicit && errors.len() > conflicts {
+        let note = "without any expressions dice is interactive, give - to read stdin";
+        errors.push(note.to_string());
+    }
+
+    if errors.is_empty() {
+        Ok(options)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod cli_test {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn exprs(s: &str) -> Vec<Input> {
+        s.split_whitespace().map(|s| Input::Expr(s.to_string())).collect()
+    }
+
+    #[test]
+    fn can_parse_options() {
+        let options = parse(args("--seed 7 3d6 --dist 1d4 --keep-going")).unwrap();
+        assert_eq!(Some(7), options.seed);
+        assert_eq!(Some(Table::Exactly), options.table);
+        assert!(options.keep_going);
+        assert_eq!(exprs("3d6 1d4"), options.inputs);
+
+        let options = parse(args("--nonce=3 --at-most --export=json -- --seed")).unwrap();
+        assert_eq!(3, options.nonce);
+        assert_eq!(Some(Table::AtMost), options.table);
+        assert_eq!(Export::Json, options.export);
+        assert_eq!(exprs("--seed"), options.inputs);
+
+        let options = parse(args("--format ndjson 1d6")).unwrap();
+        assert_eq!(Format::Ndjson, options.format);
+
+        let options = parse(args("--sort desc --aggregate 6x4d6kh3")).unwrap();
+        assert_eq!(Some(Order::Descending), options.order);
+        assert!(options.aggregate);
+
+        let options = parse(args("-i --seed 3 1d6")).unwrap();
+        assert!(options.interactive);
+        assert_eq!(exprs("1d6"), options.inputs);
+
+        let options = parse(args("1d4 --file rolls.txt - 1d6")).unwrap();
+        let file = Input::File("rolls.txt".to_string());
+        let (first, last) = (Input::Expr("1d4".into()), Input::Expr("1d6".into()));
+        assert_eq!(vec![first, file, Input::Stdin, last], options.inputs);
+    }
+
+    #[test]
+    fn reports_every_invalid_option() {
+        let errors = parse(args("--seed x 1d6 --frobnicate --dist=1 --export xml --nonce"));
+        let errors = errors.unwrap_err();
+        assert_eq!(5, errors.len());
+        assert!(errors[0].contains("'x'"));
+        assert_eq!("unknown option '--frobnicate'", errors[1]);
+        assert_eq!("--dist does not take a value", errors[2]);
+        assert!(errors[3].contains("'xml'"));
+        assert_eq!("--nonce needs a value", errors[4]);
+
+        assert_eq!(1, parse(args("--client-seed me 1d20")).unwrap_err().len());
+        assert_eq!(1, parse(args("-i --dist")).unwrap_err().len());
+        assert_eq!(1, parse(args("--format yaml 1d6")).unwrap_err().len());
+        assert_eq!(1, parse(args("-i -")).unwrap_err().len());
+        assert_eq!(1, parse(args("--file")).unwrap_err().len());
+        assert_eq!(1, parse(args("--format json --dist 1d6")).unwrap_err().len());
+        assert_eq!(1, parse(args("--format csv --aggregate 6x1d6")).unwrap_err().len());
+        assert_eq!(1, parse(args("--sort up 6x1d6")).unwrap_err().len());
+        assert_eq!(vec!["unknown option '-k'"], parse(args("-k 1d6")).unwrap_err());
+        assert_eq!(vec!["unknown option '-d6'"], parse(args("-d6")).unwrap_err());
+    }
+
+    #[test]
+    fn no_expressions_is_interactive() {
+        let options = parse(args("--seed 3 --sort asc")).unwrap();
+        assert!(options.interactive);
+        assert!(!parse(args("--help")).unwrap().interactive);
+
+        let fair = format!("--server-seed {} --client-seed me", "0".repeat(64));
+        let errors = parse(args(&fair)).unwrap_err();
+        assert_eq!(2, errors.len());
+        assert_eq!("interactive mode only rolls, with --seed if given", errors[0]);
+        assert!(parse(args(&(fair + " -"))).is_ok());
+        assert_eq!(2, parse(args("--dist")).unwrap_err().len());
+        assert_eq!(2, parse(args("--format json")).unwrap_err().len());
+    }
+
+    #[test]
+    fn pools_hint_at_queries() {
+        let hint = "2d6>=10 counts the dice rolling >=10, write `2d6 >= 10` to compare the total";
+        assert_eq!(Some(hint.to_string()), query_hint("2d6>=10"));
+        assert!(query_hint("4d6!>=5<3").unwrap().contains("`4d6!>=5 < 3`"));
+        assert_eq!(None, query_hint("8d10>=7f1"));
+        assert_eq!(None, query_hint("2d6 >="));
+    }
+
+    #[test]
+    fn short_options() {
+        assert!(parse(args("-h")).unwrap().help);
+        assert!(parse(args("--help")).unwrap().help);
+        assert!(parse(args("-V")).unwrap().version);
+        assert!(parse(args("--version")).unwrap().version);
+        assert_eq!(vec!["unknown option '-1d4'"], parse(args("-1d4")).unwrap_err());
+        assert_eq!(exprs("-d6"), parse(args("-- -d6")).unwrap().inputs);
+    }
+
+    #[test]
+    fn errors_have_distinct_codes() {
+        let parse_error = "1d".parse::<rcmd::Expr>().unwrap_err();
+        assert_eq!(EXIT_PARSE, parse_code(&parse_error));
+        let limit_error = "1000000d6".parse::<rcmd::Expr>().unwrap_err();
+        assert_eq!(EXIT_LIMIT, parse_code(&limit_error));
+        assert_eq!(EXIT_RNG, roll_code(&RollError::Exhausted));
+        assert_eq!(EXIT_FAILURE, roll_code(&RollError::Overflow));
+    }
+}
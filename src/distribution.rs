@@ -0,0 +1,727 @@
+//! Exact probability distributions of roll expressions.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use error::RollError;
+use limits::Budget;
+use {Compare, ExplodeMode, Keep, RollCommand};
+
+/// The chance of every total a roll can come up with.
+///
+/// Distributions are computed exactly by convolving the chances of single
+/// dice, without sampling; they are only as precise as the `f64` holding
+/// each chance.
+///
+/// # Examples
+/// ```
+/// use rcmd::Expr;
+///
+/// let expr: Expr = "2d6".parse().unwrap();
+/// let dist = expr.distribution().unwrap();
+///
+/// assert!(2 == dist.min() && 12 == dist.max());
+/// assert!(7 == dist.mode());
+/// assert!((dist.mean() - 7.0).abs() < 1e-9);
+/// assert!((dist.probability(7) - 6.0 / 36.0).abs() < 1e-9);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Distribution {
+    min: i128,
+    chances: Vec<f64>,
+}
+
+/// Which chance of every value `Distribution::table` lists.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Table {
+    /// The chance of rolling exactly the value.
+    Exactly,
+    /// The chance of rolling the value or higher.
+    AtLeast,
+    /// The chance of rolling the value or lower.
+    AtMost,
+}
+
+/// How the dice of a roll make up its total.
+pub(crate) enum Count {
+    /// The values of the kept dice add up.
+    Sum,
+    /// Kept faces matching the first comparison are successes, and those
+    /// matching the second, if any, cancel a success out.
+    Successes(Compare, Option<Compare>),
+}
+
+impl Count {
+    fn score(&self, value: i128) -> i128 {
+        match *self {
+            Count::Sum => value,
+            Count::Successes(success, failure) => {
+                let failed = failure.is_some_and(|f| f.matches(value));
+                i128::from(success.matches(value)) - i128::from(failed)
+            }
+        }
+    }
+}
+
+/// Books a distribution from `min` to `max`, returning its length.
+fn span(min: i128, max: i128, budget: &mut Budget) -> Result<usize, RollError> {
+    let span = max.checked_sub(min).and_then(|n| n.checked_add(1)).ok_or(RollError::Overflow)?;
+    budget.chances(u64::try_from(span).unwrap_or(u64::MAX))?;
+    Ok(span as usize)
+}
+
+impl Distribution {
+    /// Constructs a distribution that always comes up `value`.
+    pub fn constant(value: i128) -> Distribution {
+        Distribution { min: value, chances: vec![1.0] }
+    }
+
+    /// Constructs a distribution without any chances, to add others to.
+    fn empty() -> Distribution {
+        Distribution { min: 0, chances: Vec::new() }
+    }
+
+    /// Collects pairs of a value and its chance into a distribution.
+    fn collect(chances: &[(i128, f64)], budget: &mut Budget) -> Result<Distribution, RollError> {
+        let min = chances.iter().map(|&(value, _)| value).min();
+        let max = chances.iter().map(|&(value, _)| value).max();
+        let (min, max) = match (min, max) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Ok(Distribution::empty()),
+        };
+        let mut dist = Distribution { min, chances: vec![0.0; span(min, max, budget)?] };
+        for &(value, chance) in chances {
+            dist.chances[(value - min) as usize] += chance;
+        }
+        Ok(dist.trimmed())
+    }
+
+    /// Drops the values without any chance from both ends.
+    fn trimmed(mut self) -> Distribution {
+        match self.chances.iter().position(|&p| p > 0.0) {
+            Some(first) => {
+                let last = self.chances.iter().rposition(|&p| p > 0.0).unwrap_or(first);
+                self.chances.truncate(last + 1);
+                self.chances.drain(..first);
+                self.min += first as i128;
+                self
+            }
+            None => Distribution::empty(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chances.is_empty()
+    }
+
+    /// Returns the chance of coming up with any value at all, which is 1
+    /// for every distribution handed out.
+    fn mass(&self) -> f64 {
+        self.chances.iter().sum()
+    }
+
+    /// Returns the indices of the values that have a chance.
+    fn support(&self) -> Vec<usize> {
+        (0..self.chances.len()).filter(|&i| self.chances[i] > 0.0).collect()
+    }
+
+    /// Adds the chances of `other`, weighted by `weight`, to these.
+    fn mix(
+        &mut self,
+        other: &Distribution,
+        weight: f64,
+        budget: &mut Budget,
+    ) -> Result<(), RollError> {
+        if other.is_empty() {
+            return Ok(());
+        }
+        if self.is_empty() {
+            *self = Distribution { min: other.min, chances: vec![0.0; other.chances.len()] };
+        }
+        let min = self.min.min(other.min);
+        let max = self.max().max(other.max());
+        let mut chances = vec![0.0; span(min, max, budget)?];
+        let offset = (self.min - min) as usize;
+        chances[offset..offset + self.chances.len()].copy_from_slice(&self.chances);
+        let offset = (other.min - min) as usize;
+        for (i, &p) in other.chances.iter().enumerate() {
+            chances[offset + i] += p * weight;
+        }
+        *self = Distribution { min, chances };
+        Ok(())
+    }
+
+    /// Returns the distribution of the sum of a value drawn from this
+    /// distribution and one drawn from `other`.
+    pub(crate) fn convolve(
+        &self,
+        other: &Distribution,
+        budget: &mut Budget,
+    ) -> Result<Distribution, RollError> {
+        if self.is_empty() || other.is_empty() {
+            return Ok(Distribution::empty());
+        }
+        let min = self.min.checked_add(other.min).ok_or(RollError::Overflow)?;
+        let max = self.max().checked_add(other.max()).ok_or(RollError::Overflow)?;
+        let mut chances = vec![0.0; span(min, max, budget)?];
+        let (lhs, rhs) = (self.support(), other.support());
+        budget.chances((lhs.len() as u64).saturating_mul(rhs.len() as u64))?;
+        for &i in &lhs {
+            for &j in &rhs {
+                chances[i + j] += self.chances[i] * other.chances[j];
+            }
+        }
+        Ok(Distribution { min, chances })
+    }
+
+    /// Returns the distribution of the product of a value drawn from this
+    /// distribution and one drawn from `other`.
+    pub(crate) fn product(
+        &self,
+        other: &Distribution,
+        budget: &mut Budget,
+    ) -> Result<Distribution, RollError> {
+        if self.is_empty() || other.is_empty() {
+            return Ok(Distribution::empty());
+        }
+        let mut corners = Vec::new();
+        for &a in &[self.min, self.max()] {
+            for &b in &[other.min, other.max()] {
+                corners.push(a.checked_mul(b).ok_or(RollError::Overflow)?);
+            }
+        }
+        let min = *corners.iter().min().expect("there are four corners");
+        let max = *corners.iter().max().expect("there are four corners");
+        let mut chances = vec![0.0; span(min, max, budget)?];
+        let (lhs, rhs) = (self.support(), other.support());
+        budget.chances((lhs.len() as u64).saturating_mul(rhs.len() as u64))?;
+        for &i in &lhs {
+            for &j in &rhs {
+                let value = (self.min + i as i128) * (other.min + j as i128);
+                chances[(value - min) as usize] += self.chances[i] * other.chances[j];
+            }
+        }
+        Ok(Distribution { min, chances }.trimmed())
+    }
+
+    /// Returns the distribution of the negated values.
+    pub(crate) fn negate(&self) -> Result<Distribution, RollError> {
+        let min = self.max().checked_neg().ok_or(RollError::Overflow)?;
+        let chances = self.chances.iter().rev().cloned().collect();
+        Ok(Distribution { min, chances })
+    }
+
+    /// Returns the distribution of the sum of `n` values drawn from this
+    /// distribution.
+    fn repeat(&self, mut n: u32, budget: &mut Budget) -> Result<Distribution, RollError> {
+        let mut sum = Distribution::constant(0);
+        let mut power = self.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                sum = sum.convolve(&power, budget)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                power = power.convolve(&power, budget)?;
+            }
+        }
+        Ok(sum)
+    }
+
+    /// Returns the lowest value with a chance of coming up.
+    pub fn min(&self) -> i128 {
+        self.min
+    }
+
+    /// Returns the highest value with a chance of coming up.
+    pub fn max(&self) -> i128 {
+        self.min + self.chances.len() as i128 - 1
+    }
+
+    /// Returns the chance of coming up with exactly `value`.
+    pub fn probability(&self, value: i128) -> f64 {
+        match value.checked_sub(self.min).map(usize::try_from) {
+            Some(Ok(i)) if i < self.chances.len() => self.chances[i],
+            _ => 0.0,
+        }
+    }
+
+    /// Iterates over the values with a chance of coming up, lowest first,
+    /// along with their chance.
+    pub fn iter(&self) -> impl Iterator<Item = (i128, f64)> + '_ {
+        let min = self.min;
+        self.chances
+            .iter()
+            .enumerate()
+            .filter(|&(_, &p)| p > 0.0)
+            .map(move |(i, &p)| (min + i as i128, p))
+    }
+
+    /// Returns the chance of coming up with a value that matches `compare`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Compare, RollCommand};
+    ///
+    /// let dist = RollCommand::new(2, 6).distribution().unwrap();
+    /// assert!((dist.chance(Compare::Ge(10)) - 6.0 / 36.0).abs() < 1e-9);
+    /// ```
+    pub fn chance(&self, compare: Compare) -> f64 {
+        self.iter().filter(|&(value, _)| compare.matches(value)).map(|(_, p)| p).sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(value, p)| value as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter().map(|(value, p)| (value as f64 - mean).powi(2) * p).sum()
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Returns the most likely value, the lowest one if several are
+    /// equally likely.
+    pub fn mode(&self) -> i128 {
+        let mut mode = (self.min, 0.0);
+        for (value, p) in self.iter() {
+            if p > mode.1 {
+                mode = (value, p);
+            }
+        }
+        mode.0
+    }
+
+    /// Returns the lowest value that at least `percent` percent of all
+    /// rolls come up at or below, so that `percentile(50.0)` is the median.
+    ///
+    /// # Panics
+    /// Panics if `percent` is not between 0 and 100.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let dist = RollCommand::new(1, 100).distribution().unwrap();
+    /// assert!(50 == dist.percentile(50.0));
+    /// assert!(90 == dist.percentile(90.0));
+    /// assert!(100 == dist.percentile(100.0));
+    /// ```
+    pub fn percentile(&self, percent: f64) -> i128 {
+        assert!((0.0..=100.0).contains(&percent), "percentiles are between 0 and 100");
+        // leave room for the rounding of the chances summed up
+        let target = percent / 100.0 - 1e-9;
+        let mut below = 0.0;
+        for (value, p) in self.iter() {
+            below += p;
+            if below >= target {
+                return value;
+            }
+        }
+        self.max()
+    }
+
+    /// Lists every value with a chance of coming up, lowest first, along
+    /// with the chance of rolling it, at least it or at most it.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{RollCommand, Table};
+    ///
+    /// let dist = RollCommand::new(1, 4).distribution().unwrap();
+    /// let at_least: Vec<_> = dist.table(Table::AtLeast).iter().map(|&(_, p)| p).collect();
+    /// assert!(at_least == [1.0, 0.75, 0.5, 0.25]);
+    /// ```
+    pub fn table(&self, table: Table) -> Vec<(i128, f64)> {
+        let mut rows: Vec<_> = self.iter().collect();
+        let cumulate = |rows: &mut dyn Iterator<Item = &mut (i128, f64)>| {
+            let mut sum = 0.0;
+            for row in rows {
+                sum += row.1;
+                row.1 = sum.min(1.0);
+            }
+        };
+        match table {
+            Table::Exactly => {}
+            Table::AtLeast => cumulate(&mut rows.iter_mut().rev()),
+            Table::AtMost => cumulate(&mut rows.iter_mut()),
+        }
+        rows
+    }
+
+    /// Draws a table of the distribution as a bar chart, with one line for
+    /// every value: the value, its chance in percent and a bar that is
+    /// `width` characters long for the highest chance.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{RollCommand, Table};
+    ///
+    /// let dist = RollCommand::new(1, 2).distribution().unwrap();
+    /// let chart = "1  50.00% ##########\n2  50.00% ##########\n";
+    /// assert!(chart == dist.histogram(Table::Exactly, 10));
+    /// ```
+    pub fn histogram(&self, table: Table, width: usize) -> String {
+        let rows = self.table(table);
+        let highest = rows.iter().map(|&(_, p)| p).fold(0.0, f64::max);
+        let digits = rows.iter().map(|&(value, _)| value.to_string().len()).max().unwrap_or(1);
+        let mut chart = String::new();
+        for (value, p) in rows {
+            let bar = "#".repeat((p / highest * width as f64).round() as usize);
+            chart += &format!("{:>digits$} {:>6.2}% {}\n", value, p * 100.0, bar);
+        }
+        chart
+    }
+}
+
+/// Returns the chances of every face after rerolling.
+fn reroll(
+    base: &[(i64, f64)],
+    on: Compare,
+    depth: u32,
+    budget: &mut Budget,
+) -> Result<Vec<(i64, f64)>, RollError> {
+    let rerolled: f64 = base.iter().filter(|&&(face, _)| on.matches(face)).map(|&(_, p)| p).sum();
+    // with one reroll left, a die keeps the faces that do not match and
+    // rolls the faces that do again, with one reroll less
+    let mut faces = base.to_vec();
+    for _ in 0..depth {
+        budget.chances(base.len() as u64)?;
+        faces = base
+            .iter()
+            .zip(&faces)
+            .map(|(&(face, p), &(_, again))| {
+                let kept = if on.matches(face) { 0.0 } else { p };
+                (face, kept + rerolled * again)
+            })
+            .collect();
+    }
+    Ok(faces)
+}
+
+/// Returns the chances of every value a single die of `cmd` can come up
+/// with, along with the distribution of what it adds to the total.
+///
+/// Explosion chains are followed up to the depth of the explosion or the
+/// explosion depth limit, whichever is lower, and stop there.
+fn die(
+    cmd: &RollCommand,
+    faces: &[(i64, f64)],
+    base: &[(i64, f64)],
+    count: &Count,
+    budget: &mut Budget,
+) -> Result<Vec<(i128, Distribution)>, RollError> {
+    let compound = cmd.explode.map(|e| e.mode()) == Some(ExplodeMode::Compound);
+    // the score of every face is counted, unless the explosion compounds
+    let per_face = match *count {
+        Count::Sum => false,
+        Count::Successes(..) => !compound,
+    };
+    let score = |value: i128| if per_face { count.score(value) } else { 0 };
+    let triggers = |face: i64| cmd.explode.is_some_and(|e| e.triggers(face, &cmd.faces));
+
+    // chances by the value of the die and its score so far
+    let mut done = BTreeMap::new();
+    let mut alive = BTreeMap::new();
+    for &(face, p) in faces {
+        let into = if triggers(face) { &mut alive } else { &mut done };
+        *into.entry((i128::from(face), score(i128::from(face)))).or_insert(0.0) += p;
+    }
+    if let Some(explode) = cmd.explode {
+        for _ in 0..explode.depth.min(budget.limits.explosion_depth) {
+            if alive.is_empty() {
+                break;
+            }
+            let mut next = BTreeMap::new();
+            for (&(value, scored), &p) in &alive {
+                budget.chances(base.len() as u64)?;
+                for &(face, q) in base {
+                    let added = match explode.mode() {
+                        ExplodeMode::Penetrate => i128::from(face) - 1,
+                        _ => i128::from(face),
+                    };
+                    let into = if triggers(face) { &mut next } else { &mut done };
+                    *into.entry((value + added, scored + score(added))).or_insert(0.0) += p * q;
+                }
+            }
+            alive = next;
+        }
+    }
+    for (state, p) in alive {
+        *done.entry(state).or_insert(0.0) += p;
+    }
+
+    let mut by_value: BTreeMap<i128, Vec<(i128, f64)>> = BTreeMap::new();
+    for ((value, scored), p) in done {
+        let added = if per_face { scored } else { count.score(value) };
+        by_value.entry(value).or_default().push((added, p));
+    }
+    let mut outcomes = Vec::new();
+    for (value, added) in by_value {
+        outcomes.push((value, Distribution::collect(&added, budget)?));
+    }
+    Ok(outcomes)
+}
+
+/// Returns the distribution of the total of `count` dice with the given
+/// outcomes, of which only those selected by `keep` count.
+///
+/// Goes through the values of a die from the first one kept to the last,
+/// keeping track of the chances of the total for every number of dice
+/// that came up with one of the values so far.
+fn keep_dice(
+    outcomes: &[(i128, Distribution)],
+    count: u32,
+    keep: Keep,
+    budget: &mut Budget,
+) -> Result<Distribution, RollError> {
+    let (highest, n) = keep.kept(count);
+    let (count, n) = (count as usize, n as usize);
+    let mut order: Vec<&Distribution> = outcomes
+        .iter()
+        .map(|(_, added)| added)
+        .filter(|added| added.mass() > 0.0)
+        .collect();
+    if highest {
+        order.reverse();
+    }
+
+    let mut totals = vec![Distribution::empty(); count + 1];
+    totals[0] = Distribution::constant(0);
+    for (i, added) in order.iter().enumerate() {
+        let p = added.mass();
+        let last = i + 1 == order.len();
+        let chances = added.chances.iter().map(|q| q / p).collect();
+        let one = Distribution { min: added.min, chances };
+        // kept[t] is the distribution of what t kept dice of this value add
+        let mut kept = vec![Distribution::constant(0)];
+        let mut next = vec![Distribution::empty(); count + 1];
+        for a in 0..=count {
+            if totals[a].is_empty() {
+                continue;
+            }
+            // the chance that c of the remaining dice come up with this
+            // value: (count - a choose c) * p^c
+            let mut weight = 1.0;
+            for c in 0..=count - a {
+                if c > 0 {
+                    weight *= (count - a - c + 1) as f64 / c as f64 * p;
+                }
+                if last && a + c < count {
+                    continue;
+                }
+                let t = (a + c).min(n) - a.min(n);
+                while kept.len() <= t {
+                    let more = kept[kept.len() - 1].convolve(&one, budget)?;
+                    kept.push(more);
+                }
+                let total = totals[a].convolve(&kept[t], budget)?;
+                next[a + c].mix(&total, weight, budget)?;
+            }
+        }
+        totals = next;
+    }
+    Ok(totals.pop().expect("there is a total for every number of dice").trimmed())
+}
+
+/// Returns the distribution of the total of `cmd`, added up as `count`
+/// says.
+pub(crate) fn roll(
+    cmd: &RollCommand,
+    count: &Count,
+    budget: &mut Budget,
+) -> Result<Distribution, RollError> {
+    cmd.check()?;
+    let sides = cmd.faces.sides();
+    budget.dice(cmd.count, sides)?;
+    if cmd.faces.is_symbolic() {
+        if let Count::Sum = *count {
+            return Ok(Distribution::constant(0));
+        }
+    }
+
+    budget.chances(u64::from(sides))?;
+    let base: Vec<(i64, f64)> = (1..=sides)
+        .filter_map(|side| cmd.faces.value(side))
+        .map(|value| (value, 1.0 / f64::from(sides)))
+        .collect();
+    let faces = match cmd.reroll {
+        Some(reroll) => self::reroll(&base, reroll.on, reroll.depth, budget)?,
+        None => base.clone(),
+    };
+    let outcomes = die(cmd, &faces, &base, count, budget)?;
+    match cmd.keep {
+        Some(keep) => keep_dice(&outcomes, cmd.count, keep, budget),
+        None => {
+            let chances: Vec<_> = outcomes.iter().flat_map(|(_, added)| added.iter()).collect();
+            Distribution::collect(&chances, budget)?.repeat(cmd.count, budget)
+        }
+    }
+}
+
+#[cfg(test)]
+mod distribution_test {
+    use super::*;
+    use limits::{Limit, Limits};
+    use {Explode, Expr, SeededSource};
+
+    fn dist(s: &str) -> Distribution {
+        s.parse::<Expr>().unwrap().distribution().unwrap()
+    }
+
+    fn close(expected: f64, actual: f64) -> bool {
+        (expected - actual).abs() < 1e-9
+    }
+
+    /// Checks the chance of every value against counts out of `outcomes`.
+    fn assert_counts(dist: &Distribution, min: i128, counts: &[u32], outcomes: f64) {
+        assert_eq!(min, dist.min());
+        assert_eq!(min + counts.len() as i128 - 1, dist.max());
+        for (i, &n) in counts.iter().enumerate() {
+            let p = dist.probability(min + i as i128);
+            assert!(close(f64::from(n) / outcomes, p), "{} at {}", p, min + i as i128);
+        }
+        assert!(close(1.0, dist.mass()));
+    }
+
+    #[test]
+    fn sums_of_dice() {
+        assert_counts(&dist("2d6"), 2, &[1, 2, 3, 4, 5, 6, 5, 4, 3, 2, 1], 36.0);
+        assert_counts(&dist("4dF"), -4, &[1, 4, 10, 16, 19, 16, 10, 4, 1], 81.0);
+        assert_counts(&dist("2d{1,1,3}"), 2, &[4, 0, 4, 0, 1], 9.0);
+
+        let dist = dist("3d6");
+        assert!(close(10.5, dist.mean()));
+        assert!(close(8.75, dist.variance()));
+        assert!(close(8.75f64.sqrt(), dist.stddev()));
+        assert_eq!(10, dist.mode());
+        assert_eq!(10, dist.percentile(50.0));
+        assert_eq!(3, dist.percentile(0.0));
+    }
+
+    #[test]
+    fn keeping_and_dropping() {
+        let advantage = dist("2d20kh1");
+        assert!(close(39.0 / 400.0, advantage.probability(20)));
+        assert!(close(13.825, advantage.mean()));
+        assert!(close(7.175, dist("2d20kl1").mean()));
+
+        let stats = dist("4d6kh3");
+        assert_eq!(stats, dist("4d6dl1"));
+        assert!(close(15869.0 / 1296.0, stats.mean()));
+        assert!(close(1.0 / 1296.0, stats.probability(3)));
+        assert!(close(21.0 / 1296.0, stats.probability(18)));
+        assert_eq!(13, stats.mode());
+
+        // keeping more dice than were rolled keeps them all
+        let all = dist("3d6kh5");
+        assert!(close(10.5, all.mean()));
+        assert!(close(27.0 / 216.0, all.probability(10)));
+    }
+
+    #[test]
+    fn exploding_and_rerolling() {
+        // a d6 exploding forever averages 3.5 * 6 / 5
+        assert!(close(4.2, dist("1d6!").mean()));
+        assert!(close(1.0 / 36.0, dist("1d6!").probability(8)));
+        assert_eq!(0.0, dist("1d6!").probability(6));
+        assert!(close(1.0 / 36.0, dist("1d6!p").probability(7)));
+        assert_eq!(dist("1d6!"), dist("1d6!!"));
+
+        let once = Explode::new(ExplodeMode::Explode).depth(1);
+        let capped = RollCommand::new(1, 6).explode(once).distribution().unwrap();
+        assert_counts(&capped, 1, &[6, 6, 6, 6, 6, 0, 1, 1, 1, 1, 1, 1], 36.0);
+
+        assert_counts(&dist("1d6ro1"), 1, &[1, 7, 7, 7, 7, 7], 36.0);
+        assert!(close(4.0, dist("1d6r1").mean()));
+    }
+
+    #[test]
+    fn counting_successes() {
+        // five dice, each a success with a chance of 0.3
+        let pool = dist("5d10>=8");
+        assert!(close(1.5, pool.mean()));
+        assert!(close(5.0 * 0.3 * 0.7, pool.variance()));
+        assert!(close(0.3f64.powi(5), pool.probability(5)));
+
+        let botch = dist("1d10>=8f1");
+        assert_counts(&botch, -1, &[1, 6, 3], 10.0);
+
+        // every face of an exploding die counts, unless it compounds
+        assert!(close(0.3 * 0.3 * 0.7, dist("1d10!>=8>=8").probability(2)));
+        assert!(close(0.3, dist("1d10!!>=8>=8").probability(1)));
+        assert_eq!(1, dist("1d10!!>=8>=8").max());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_counts(&dist("1d4 * 2 + 1"), 3, &[1, 0, 1, 0, 1, 0, 1], 4.0);
+        assert_counts(&dist("1d2 * 1d3"), 1, &[1, 2, 1, 1, 0, 1], 6.0);
+        assert_counts(&dist("1d6 - 1d6"), -5, &[1, 2, 3, 4, 5, 6, 5, 4, 3, 2, 1], 36.0);
+        assert_eq!(Distribution::constant(7), dist("3 + 4"));
+    }
+
+    #[test]
+    fn tables_and_charts() {
+        let two = dist("2d4");
+        let at_most: Vec<_> = two.table(Table::AtMost).iter().map(|&(_, p)| p * 16.0).collect();
+        assert_eq!(vec![1.0, 3.0, 6.0, 10.0, 13.0, 15.0, 16.0], at_most);
+        let at_least = two.table(Table::AtLeast);
+        assert_eq!((2, 1.0), at_least[0]);
+        assert_eq!((8, 1.0 / 16.0), at_least[6]);
+
+        let chart = two.histogram(Table::Exactly, 8);
+        let lines: Vec<_> = chart.lines().collect();
+        assert_eq!(7, lines.len());
+        assert_eq!("2   6.25% ##", lines[0]);
+        assert_eq!("5  25.00% ########", lines[3]);
+
+        // values are aligned whatever their width
+        let chart = dist("1d2 * 5 - 6").histogram(Table::AtMost, 4);
+        assert_eq!("-1  50.00% ##\n 4 100.00% ####\n", chart);
+    }
+
+    #[test]
+    fn distributions_match_rolls() {
+        let exprs = [
+            "4d6kh3",
+            "3d6!>=5",
+            "2d10!!",
+            "3d6!p + 1",
+            "4d6r<3dl1",
+            "6d10>=7f1",
+            "5d10!>=9>=8",
+            "3d{1,1,2,5}kh2",
+            "(1d8 + 2) * 2 - 1d4",
+        ];
+        for s in &exprs {
+            let expr: Expr = s.parse().unwrap();
+            let dist = expr.distribution().unwrap();
+            let mut source = SeededSource::new(17);
+            let n = 20_000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += expr.result(&mut source).unwrap().total() as f64;
+            }
+            // the sample mean lies within five standard errors
+            let error = 5.0 * dist.stddev() / f64::from(n).sqrt();
+            assert!((sum / f64::from(n) - dist.mean()).abs() < error, "{}", s);
+        }
+    }
+
+    #[test]
+    fn distributions_are_limited() {
+        let expr: Expr = "100d6".parse().unwrap();
+        let limits = Limits::default().chances(1000);
+        let err = expr.distribution_with(&limits).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Chances(1000))), err);
+
+        let err = RollCommand::new(0, 6).distribution().err();
+        assert_eq!(Some(RollError::ZeroCount), err);
+    }
+}
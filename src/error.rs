@@ -0,0 +1,227 @@
+//! Errors reported while parsing roll commands and expressions.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use limits::Limit;
+
+/// Describes why a roll command or expression could not be parsed.
+///
+/// Every variant carries the byte span of the input it refers to, so that
+/// `render` can point at the offending position.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// A character that does not fit the grammar at this position.
+    UnexpectedChar { found: char, span: Range<usize> },
+    /// The input ended in the middle of a command: "(2d6"
+    UnexpectedEnd { span: Range<usize> },
+    /// A number was expected, such as the sides of a die or the count of a
+    /// keep modifier: "2d", "4d6kh"
+    MissingCount { span: Range<usize> },
+    /// A roll without any dice: "0d6"
+    ZeroCount { span: Range<usize> },
+    /// A die without any sides: "2d0"
+    ZeroSides { span: Range<usize> },
+    /// A number too large to be represented: "99999999999d6"
+    Overflow { span: Range<usize> },
+    /// Input left over after a complete command: "2d6 6"
+    TrailingInput { span: Range<usize> },
+    /// The same kind of modifier given twice on one die: "4d6kh3kl1"
+    DuplicateModifier { span: Range<usize> },
+    /// A list of faces with both numbers and symbols: "d{1,hit}"
+    MixedFaces { span: Range<usize> },
+    /// An expression repeated without any dice to roll: "2x6", "repeat(3, 2 + 1)"
+    MissingDice { span: Range<usize> },
+    /// An expression larger than the `Limits` it was parsed with allow:
+    /// "4000000000d6"
+    LimitExceeded { limit: Limit, span: Range<usize> },
+}
+
+impl ParseError {
+    /// Returns the byte span of the input the error refers to.
+    pub fn span(&self) -> Range<usize> {
+        match *self {
+            ParseError::UnexpectedChar { ref span, .. }
+            | ParseError::UnexpectedEnd { ref span }
+            | ParseError::MissingCount { ref span }
+            | ParseError::ZeroCount { ref span }
+            | ParseError::ZeroSides { ref span }
+            | ParseError::Overflow { ref span }
+            | ParseError::TrailingInput { ref span }
+            | ParseError::DuplicateModifier { ref span }
+            | ParseError::MixedFaces { ref span }
+            | ParseError::MissingDice { ref span }
+            | ParseError::LimitExceeded { ref span, .. } => span.clone(),
+        }
+    }
+
+    /// Renders the error below the input it came from, with carets under
+    /// the offending span.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let err = "2d".parse::<RollCommand>().unwrap_err();
+    /// assert!("2d\n  ^ missing number" == err.render("2d"));
+    /// ```
+    pub fn render(&self, src: &str) -> String {
+        let span = self.span();
+        let start = src.get(..span.start).map_or(0, |s| s.chars().count());
+        let width = src.get(span.clone()).map_or(0, |s| s.chars().count()).max(1);
+        format!("{}\n{}{} {}", src, " ".repeat(start), "^".repeat(width), self)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnexpectedChar { found, .. } => write!(f, "unexpected '{}'", found),
+            ParseError::UnexpectedEnd { .. } => write!(f, "unexpected end of input"),
+            ParseError::MissingCount { .. } => write!(f, "missing number"),
+            ParseError::ZeroCount { .. } => write!(f, "a roll needs at least one die"),
+            ParseError::ZeroSides { .. } => write!(f, "dice need at least one side"),
+            ParseError::Overflow { .. } => write!(f, "number out of range"),
+            ParseError::TrailingInput { .. } => write!(f, "unexpected trailing input"),
+            ParseError::DuplicateModifier { .. } => write!(f, "duplicate modifier"),
+            ParseError::MixedFaces { .. } => write!(f, "faces mix numbers and symbols"),
+            ParseError::MissingDice { .. } => write!(f, "nothing to roll, repeat dice like 1d6"),
+            ParseError::LimitExceeded { limit, .. } => write!(f, "{}", limit),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Describes why a roll could not be made.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RollError {
+    /// A command without any dice: `RollCommand::new(0, 6)`
+    ZeroCount,
+    /// Dice without any sides: `RollCommand::new(1, 0)`
+    ZeroSides,
+    /// The function picking sides returned a side the die does not have.
+    FaceOutOfRange { side: u32, sides: u32 },
+    /// The roll went over one of the `Limits` it was made with.
+    LimitExceeded(Limit),
+    /// The total of an expression does not fit in an `i128`.
+    Overflow,
+    /// The `DieSource` ran out of sides to pick.
+    Exhausted,
+    /// A replayed roll asked for a die with a different number of sides
+    /// than the one recorded for the same pick.
+    Diverged { pick: usize, recorded: u32, sides: u32 },
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RollError::ZeroCount => write!(f, "a roll needs at least one die"),
+            RollError::ZeroSides => write!(f, "dice need at least one side"),
+            RollError::FaceOutOfRange { side, sides } => {
+                write!(f, "side {} is not between 1 and {}", side, sides)
+            }
+            RollError::LimitExceeded(limit) => write!(f, "{}", limit),
+            RollError::Overflow => write!(f, "total out of range"),
+            RollError::Exhausted => write!(f, "ran out of dice to roll"),
+            RollError::Diverged { pick, recorded, sides } => write!(
+                f,
+                "replay diverged at roll {}: recorded a d{}, asked for a d{}",
+                pick + 1,
+                recorded,
+                sides
+            ),
+        }
+    }
+}
+
+impl Error for RollError {}
+
+/// Describes why a `RollLog` could not be replayed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReplayError {
+    /// A line of a written log that could not be read, counting from 1.
+    Malformed { line: usize },
+    /// The recorded expression does not parse.
+    Parse(ParseError),
+    /// The expression replayed differs from the recorded one.
+    ExpressionChanged { recorded: String, found: String },
+    /// Replaying failed, typically because it diverged from the recorded
+    /// picks.
+    Roll(RollError),
+    /// The replay finished without using every recorded pick.
+    UnusedPicks { used: usize, recorded: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Malformed { line } => write!(f, "malformed roll log at line {}", line),
+            ReplayError::Parse(ref e) => write!(f, "recorded expression: {}", e),
+            ReplayError::ExpressionChanged { ref recorded, ref found } => {
+                write!(f, "expression changed from {} to {}", recorded, found)
+            }
+            ReplayError::Roll(ref e) => write!(f, "{}", e),
+            ReplayError::UnusedPicks { used, recorded } => {
+                write!(f, "replay used {} of {} recorded rolls", used, recorded)
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+/// Describes why a provably fair roll could not be verified.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FairError {
+    /// A seed or commitment that is not 64 hex digits.
+    Malformed,
+    /// The revealed server seed does not hash to the commitment.
+    SeedMismatch,
+    /// Rolling the expression again failed.
+    Roll(RollError),
+}
+
+impl fmt::Display for FairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FairError::Malformed => write!(f, "seeds and commitments are 64 hex digits"),
+            FairError::SeedMismatch => write!(f, "server seed does not match the commitment"),
+            FairError::Roll(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for FairError {}
+
+#[cfg(test)]
+mod error_test {
+    use super::*;
+    use {Expr, RollCommand};
+
+    fn parse_error(s: &str) -> ParseError {
+        s.parse::<Expr>().unwrap_err()
+    }
+
+    #[test]
+    fn errors_carry_spans() {
+        assert_eq!(ParseError::MissingCount { span: 2..2 }, parse_error("2d"));
+        assert_eq!(ParseError::MissingCount { span: 5..6 }, parse_error("4d6kh+1"));
+        assert_eq!(ParseError::ZeroSides { span: 2..3 }, parse_error("2d0"));
+        assert_eq!(ParseError::ZeroCount { span: 4..5 }, parse_error("1 + 0d6"));
+        assert_eq!(ParseError::Overflow { span: 0..11 }, parse_error("99999999999d6"));
+        assert_eq!(ParseError::TrailingInput { span: 4..5 }, parse_error("2d6 6"));
+        assert_eq!(ParseError::UnexpectedEnd { span: 4..4 }, parse_error("(2d6"));
+        assert_eq!(ParseError::UnexpectedChar { found: 'x', span: 0..1 }, parse_error("x"));
+        assert_eq!(ParseError::DuplicateModifier { span: 6..9 }, parse_error("4d6kh3kl1"));
+        assert_eq!(ParseError::MixedFaces { span: 1..8 }, parse_error("d{1,hit}"));
+    }
+
+    #[test]
+    fn trailing_input_spans_the_rest() {
+        let err = "2d6d8".parse::<RollCommand>().unwrap_err();
+        assert_eq!(ParseError::TrailingInput { span: 3..5 }, err);
+        assert_eq!("2d6d8\n   ^^ unexpected trailing input", err.render("2d6d8"));
+    }
+}
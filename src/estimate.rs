@@ -0,0 +1,424 @@
+//! Monte Carlo estimates for expressions too expensive to compute exactly.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use error::RollError;
+use limits::Limits;
+use source::{DieSource, SeededSource};
+use Expr;
+
+/// The number of samples drawn with the same source.
+const BATCH: u64 = 1_000;
+
+/// Derives the seed of a batch from the seed of the whole estimate, with
+/// the SplitMix64 mixing function, so that neighbouring batches draw
+/// unrelated samples.
+fn batch_seed(seed: u64, batch: u64) -> u64 {
+    let mut z = seed.wrapping_add(batch.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns the z-score that a normally distributed value stays within
+/// with a chance of `confidence`, using Acklam's approximation of the
+/// inverse normal distribution.
+fn z_score(confidence: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+
+    let p = (1.0 + confidence) / 2.0;
+    if p <= 1.0 - 0.02425 {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        let q = (-2.0 * (1.0 - p).ln()).sqrt();
+        -(((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    }
+}
+
+/// The count, mean and sum of squared deviations of some samples.
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    n: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    /// Adds a sample, with Welford's algorithm.
+    fn add(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Combines the moments of two disjoint sets of samples.
+    fn merge(self, other: Moments) -> Moments {
+        if self.n == 0 {
+            return other;
+        }
+        let n = self.n + other.n;
+        let delta = other.mean - self.mean;
+        let mean = self.mean + delta * other.n as f64 / n as f64;
+        let m2 = self.m2 + other.m2 + delta * delta * (self.n * other.n) as f64 / n as f64;
+        Moments { n, mean, m2 }
+    }
+}
+
+/// Estimates the mean of a roll by rolling it over and over.
+///
+/// The samples are drawn in batches of a thousand, each with a source of
+/// its own seeded from the seed of the estimator and the number of the
+/// batch. Batches are spread over threads but always added up in order,
+/// so the same seed gives the same estimate whatever the number of
+/// threads. Only a time budget makes the number of samples, and so the
+/// estimate, depend on how fast the machine is.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Estimator, Expr};
+///
+/// let expr: Expr = "10d6!!>=5 + 3d20kh1".parse().unwrap();
+/// let estimate = Estimator::new(42).samples(20_000).estimate(&expr).unwrap();
+/// let (low, high) = estimate.interval();
+/// assert!(20_000 == estimate.samples());
+/// assert!(low < estimate.mean() && estimate.mean() < high);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimator {
+    seed: u64,
+    samples: u64,
+    precision: Option<f64>,
+    time: Option<Duration>,
+    confidence: f64,
+    threads: usize,
+    limits: Limits,
+}
+
+impl Estimator {
+    /// Constructs an estimator drawing 100,000 samples starting from
+    /// `seed`, on as many threads as the machine runs at once, with a
+    /// confidence interval of 95 percent.
+    pub fn new(seed: u64) -> Estimator {
+        Estimator {
+            seed,
+            samples: 100_000,
+            precision: None,
+            time: None,
+            confidence: 0.95,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Returns this estimator drawing at most `n` samples.
+    ///
+    /// # Panics
+    /// Panics if `n` is 0.
+    pub fn samples(self, n: u64) -> Estimator {
+        assert!(n > 0, "an estimate needs at least one sample");
+        Estimator { samples: n, ..self }
+    }
+
+    /// Returns this estimator stopping as soon as the confidence interval
+    /// is no wider than `precision` on either side of the mean.
+    pub fn precision(self, precision: f64) -> Estimator {
+        Estimator { precision: Some(precision), ..self }
+    }
+
+    /// Returns this estimator stopping once `time` has passed, as long as
+    /// it has drawn at least one batch of samples.
+    pub fn time(self, time: Duration) -> Estimator {
+        Estimator { time: Some(time), ..self }
+    }
+
+    /// Returns this estimator reporting an interval that holds the true
+    /// mean with a chance of `confidence`, between 0 and 1.
+    ///
+    /// # Panics
+    /// Panics if `confidence` is not strictly between 0 and 1.
+    pub fn confidence(self, confidence: f64) -> Estimator {
+        assert!(confidence > 0.0 && confidence < 1.0, "confidence is between 0 and 1");
+        Estimator { confidence, ..self }
+    }
+
+    /// Returns this estimator drawing samples on `n` threads.
+    pub fn threads(self, n: usize) -> Estimator {
+        Estimator { threads: n.max(1), ..self }
+    }
+
+    /// Returns this estimator rolling within the given limits instead of
+    /// the default ones.
+    pub fn limits(self, limits: Limits) -> Estimator {
+        Estimator { limits, ..self }
+    }
+
+    /// Estimates the mean total of `expr`, rolling it with a
+    /// `SeededSource`.
+    ///
+    /// Fails with the error of the first roll that fails.
+    pub fn estimate(&self, expr: &Expr) -> Result<Estimate, RollError> {
+        let limits = self.limits;
+        self.estimate_with(SeededSource::new, |source| {
+            Ok(expr.result_with(source, &limits)?.total() as f64)
+        })
+    }
+
+    /// Estimates the mean of any function of rolls made with any source.
+    ///
+    /// `new_source` constructs the source of every batch from the seed of
+    /// the batch, and `sample` draws a single sample with it.
+    ///
+    /// Fails with the error of the first sample that fails.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Estimator, Expr, SeededSource};
+    ///
+    /// // the chance of an attack with advantage hitting armour class 15
+    /// let attack: Expr = "2d20kh1 + 5".parse().unwrap();
+    /// let estimator = Estimator::new(7).samples(10_000);
+    /// let estimate = estimator.estimate_with(SeededSource::new, |source| {
+    ///     let hit = attack.result(source)?.total() >= 15;
+    ///     Ok(if hit { 1.0 } else { 0.0 })
+    /// });
+    /// let chance = estimate.unwrap().mean();
+    /// assert!(chance > 0.77 && chance < 0.82);
+    /// ```
+    pub fn estimate_with<S, N, F>(&self, new_source: N, sample: F) -> Result<Estimate, RollError>
+    where
+        S: DieSource,
+        N: Fn(u64) -> S + Sync,
+        F: Fn(&mut S) -> Result<f64, RollError> + Sync,
+    {
+        let start = Instant::now();
+        let batches = self.samples.div_ceil(BATCH);
+        let next = AtomicU64::new(0);
+        let stop = AtomicBool::new(false);
+        let z = z_score(self.confidence);
+        let (tx, rx) = mpsc::channel();
+
+        let run_batch = |batch: u64| {
+            let mut source = new_source(batch_seed(self.seed, batch));
+            let mut moments = Moments::default();
+            for _ in batch * BATCH..self.samples.min((batch + 1) * BATCH) {
+                moments.add(sample(&mut source)?);
+            }
+            Ok(moments)
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let tx = tx.clone();
+                let (next, stop, run_batch) = (&next, &stop, &run_batch);
+                scope.spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let batch = next.fetch_add(1, Ordering::Relaxed);
+                        if batch >= batches || tx.send((batch, run_batch(batch))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            // add the batches up in order, whatever order they finish in
+            let mut pending = BTreeMap::new();
+            let mut total = Moments::default();
+            let mut added = 0;
+            let mut result = Ok(());
+            'batches: for (batch, moments) in rx {
+                pending.insert(batch, moments);
+                while let Some(moments) = pending.remove(&added) {
+                    added += 1;
+                    total = match moments {
+                        Ok(moments) => total.merge(moments),
+                        Err(e) => {
+                            result = Err(e);
+                            break 'batches;
+                        }
+                    };
+                    let estimate = Estimate::new(total, self.confidence, z);
+                    let precise = self.precision.is_some_and(|p| estimate.margin() <= p);
+                    let late = self.time.is_some_and(|time| start.elapsed() >= time);
+                    if added == batches || precise || late {
+                        break 'batches;
+                    }
+                }
+            }
+            stop.store(true, Ordering::Relaxed);
+            result.map(|()| Estimate::new(total, self.confidence, z))
+        })
+    }
+}
+
+/// The outcome of an `Estimator`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Estimate {
+    samples: u64,
+    mean: f64,
+    variance: f64,
+    confidence: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    z: f64,
+}
+
+impl Estimate {
+    fn new(moments: Moments, confidence: f64, z: f64) -> Estimate {
+        let variance = if moments.n > 1 { moments.m2 / (moments.n - 1) as f64 } else { 0.0 };
+        Estimate { samples: moments.n, mean: moments.mean, variance, confidence, z }
+    }
+
+    /// Returns the number of samples the estimate is made from.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Returns the variance of the samples.
+    pub fn variance(&self) -> f64 {
+        self.variance
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    /// Returns the standard error of the mean.
+    pub fn standard_error(&self) -> f64 {
+        (self.variance / self.samples as f64).sqrt()
+    }
+
+    /// Returns the chance that the confidence interval holds the true mean.
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    /// Returns how far the confidence interval reaches on either side of
+    /// the mean.
+    pub fn margin(&self) -> f64 {
+        self.z * self.standard_error()
+    }
+
+    /// Returns the lower and upper end of the confidence interval.
+    pub fn interval(&self) -> (f64, f64) {
+        (self.mean - self.margin(), self.mean + self.margin())
+    }
+}
+
+#[cfg(test)]
+mod estimate_test {
+    use super::*;
+    use limits::Limit;
+
+    fn expr(s: &str) -> Expr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn z_scores() {
+        assert!((z_score(0.95) - 1.959_964).abs() < 1e-6);
+        assert!((z_score(0.99) - 2.575_829).abs() < 1e-6);
+        assert!((z_score(0.999_999) - 4.891_638).abs() < 1e-5);
+    }
+
+    #[test]
+    fn estimates_are_deterministic() {
+        let expr = expr("4d6kh3 + 1d8!");
+        let estimator = Estimator::new(18).samples(10_500);
+        let once = estimator.threads(1).estimate(&expr).unwrap();
+        let many = estimator.threads(4).estimate(&expr).unwrap();
+        assert_eq!(once, many);
+        assert_eq!(10_500, once.samples());
+        assert!(once != Estimator::new(19).samples(10_500).estimate(&expr).unwrap());
+    }
+
+    #[test]
+    fn estimates_hold_the_exact_mean() {
+        for s in &["4d6kh3", "3d6!>=5 * 2", "8d10>=7f1", "2d20kl1 - 1d4"] {
+            let expr = expr(s);
+            let exact = expr.distribution().unwrap();
+            let estimator = Estimator::new(3).samples(50_000).confidence(0.999_999);
+            let (low, high) = estimator.estimate(&expr).unwrap().interval();
+            assert!(low < exact.mean() && exact.mean() < high, "{}", s);
+        }
+    }
+
+    #[test]
+    fn estimates_stop_early() {
+        let expr = expr("3d6");
+        let estimate = Estimator::new(1).samples(10_000_000).precision(0.05).estimate(&expr);
+        let estimate = estimate.unwrap();
+        assert!(estimate.margin() <= 0.05);
+        assert!(estimate.samples() < 100_000);
+        assert_eq!(0, estimate.samples() % BATCH);
+
+        let estimator = Estimator::new(1).samples(u64::MAX).time(Duration::from_millis(10));
+        assert!(estimator.estimate(&expr).unwrap().samples() < u64::MAX);
+    }
+
+    #[test]
+    fn estimates_use_any_source() {
+        let expr = expr("2d6 + 1");
+        let estimator = Estimator::new(0).samples(2_500);
+        let estimate = estimator.estimate_with(|_| |max| max, |source| {
+            Ok(expr.result(source)?.total() as f64)
+        });
+        let estimate = estimate.unwrap();
+        assert_eq!(13.0, estimate.mean());
+        assert_eq!(0.0, estimate.variance());
+    }
+
+    #[test]
+    fn estimates_report_failed_rolls() {
+        let limits = Limits::default().steps(5);
+        let estimate = Estimator::new(0).limits(limits).estimate(&expr("10d6"));
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Steps(5))), estimate.err());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one sample")]
+    fn estimates_need_samples() {
+        Estimator::new(0).samples(0);
+    }
+}
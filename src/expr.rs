@@ -0,0 +1,440 @@
+//! Arithmetic roll expressions such as `2d6 + 1d4 + 3` or `(1d8 + 2) * 2`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use distribution::Distribution;
+use error::{ParseError, RollError};
+use limits::{Budget, Limit, Limits};
+use parse;
+use source::DieSource;
+use {PoolCommand, PoolResult, RollCommand, RollResult};
+
+/// Binary operators available in roll expressions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl Op {
+    /// Applies the operator, or returns None if the result overflows.
+    fn apply(self, lhs: i128, rhs: i128) -> Option<i128> {
+        match self {
+            Op::Add => lhs.checked_add(rhs),
+            Op::Sub => lhs.checked_sub(rhs),
+            Op::Mul => lhs.checked_mul(rhs),
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul => 2,
+        }
+    }
+
+    /// Whether an operand built with `child` needs parentheses when printed
+    /// on the given side of this operator.
+    ///
+    /// Operators group from the left, so a right operand of the same
+    /// precedence keeps its parentheses: "1 + (2 - 3)"
+    fn needs_parens(self, child: Op, right: bool) -> bool {
+        child.precedence() < self.precedence()
+            || (right && child.precedence() == self.precedence())
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match *self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+        };
+        f.write_str(symbol)
+    }
+}
+
+/// A parsed roll expression.
+///
+/// Expressions are trees of dice rolls, success-counting pools and integer
+/// constants combined with `+`, `-` and `*`. Multiplication binds tighter than addition and
+/// subtraction, and parentheses can be used for grouping.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Expr {
+    Roll(RollCommand),
+    Pool(PoolCommand),
+    Constant(i64),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression, rolling every dice term along the way.
+    ///
+    /// `source` is used the same way as by `RollCommand::result`, and
+    /// picks the sides of the dice in left to right order. Fails on the
+    /// first roll that fails, and if the total of any part of the
+    /// expression overflows.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::Expr;
+    ///
+    /// let expr: Expr = "(1d8 + 2) * 2".parse().unwrap();
+    /// let result = expr.result(&mut |max| max).unwrap();
+    /// assert!(20 == result.total());
+    /// ```
+    pub fn result<S>(&self, source: &mut S) -> Result<ExprResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.result_with(source, &Limits::default())
+    }
+
+    /// Evaluates the expression like `result`, but within the given limits
+    /// instead of the default ones.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Expr, Limit, Limits, RollError};
+    ///
+    /// let expr: Expr = "60d6 + 60d6".parse().unwrap();
+    /// let limits = Limits::default().total_dice(100);
+    /// let err = expr.result_with(&mut |max| max, &limits).err();
+    /// assert!(Some(RollError::LimitExceeded(Limit::TotalDice(100))) == err);
+    /// ```
+    pub fn result_with<S>(&self, source: &mut S, limits: &Limits) -> Result<ExprResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        if self.nodes() > limits.nodes {
+            return Err(RollError::LimitExceeded(Limit::Nodes(limits.nodes)));
+        }
+        self.eval(source, &mut Budget::new(limits))
+    }
+
+    /// Computes the exact chance of every total the expression can come up
+    /// with, within the default `Limits`.
+    ///
+    /// The terms of an expression are rolled independently of each other,
+    /// so their distributions are combined term by term.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::Expr;
+    ///
+    /// // how likely is a fireball to take down an ogre with 59 hit points?
+    /// let expr: Expr = "8d6".parse().unwrap();
+    /// let dist = expr.distribution().unwrap();
+    /// let chance: f64 = dist.iter().filter(|&(n, _)| n >= 59).map(|(_, p)| p).sum();
+    /// assert!(chance < 0.0001);
+    /// ```
+    pub fn distribution(&self) -> Result<Distribution, RollError> {
+        self.distribution_with(&Limits::default())
+    }
+
+    /// Computes the distribution like `distribution`, but within the given
+    /// limits instead of the default ones.
+    pub fn distribution_with(&self, limits: &Limits) -> Result<Distribution, RollError> {
+        if self.nodes() > limits.nodes {
+            return Err(RollError::LimitExceeded(Limit::Nodes(limits.nodes)));
+        }
+        self.pmf(&mut Budget::new(limits))
+    }
+
+    /// Parses an expression like `str::parse`, but within the given limits
+    /// instead of the default ones.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<Expr, ParseError> {
+        parse::expression(s, limits)
+    }
+
+    /// Returns the number of terms and operators in the expression.
+    pub(crate) fn nodes(&self) -> usize {
+        match *self {
+            Expr::Binary(_, ref lhs, ref rhs) => 1 + lhs.nodes() + rhs.nodes(),
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn eval<S>(
+        &self,
+        source: &mut S,
+        budget: &mut Budget,
+    ) -> Result<ExprResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        Ok(match *self {
+            Expr::Roll(ref cmd) => ExprResult::Roll(cmd.eval(source, budget)?),
+            Expr::Pool(ref pool) => ExprResult::Pool(pool.eval(source, budget)?),
+            Expr::Constant(n) => ExprResult::Constant(n),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                budget.step()?;
+                let lhs = lhs.eval(source, budget)?;
+                let rhs = rhs.eval(source, budget)?;
+                if op.apply(lhs.total(), rhs.total()).is_none() {
+                    return Err(RollError::Overflow);
+                }
+                ExprResult::Binary(op, Box::new(lhs), Box::new(rhs))
+            }
+        })
+    }
+
+    pub(crate) fn pmf(&self, budget: &mut Budget) -> Result<Distribution, RollError> {
+        Ok(match *self {
+            Expr::Roll(ref cmd) => cmd.pmf(budget)?,
+            Expr::Pool(ref pool) => pool.pmf(budget)?,
+            Expr::Constant(n) => Distribution::constant(i128::from(n)),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.pmf(budget)?;
+                let rhs = rhs.pmf(budget)?;
+                match op {
+                    Op::Add => lhs.convolve(&rhs, budget)?,
+                    Op::Sub => lhs.convolve(&rhs.negate()?, budget)?,
+                    Op::Mul => lhs.product(&rhs, budget)?,
+                }
+            }
+        })
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parent: Op, right: bool) -> fmt::Result {
+        match *self {
+            Expr::Binary(op, _, _) if parent.needs_parens(op, right) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression in dice notation, with only the parentheses
+    /// needed to keep its shape.
+    ///
+    /// # Examples
+    /// Binary(Mul, Binary(Add, 1d8, 2), 2) => "(1d8 + 2) * 2"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Roll(ref cmd) => write!(f, "{}", cmd),
+            Expr::Pool(ref pool) => write!(f, "{}", pool),
+            Expr::Constant(n) => write!(f, "{}", n),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                lhs.fmt_operand(f, op, false)?;
+                write!(f, " {} ", op)?;
+                rhs.fmt_operand(f, op, true)
+            }
+        }
+    }
+}
+
+/// Converts a string roll expression to an expression tree, within the
+/// default `Limits`.
+///
+/// "2d6 + 3" => Binary(Add, Roll(2d6), Constant(3)), etc
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Expr, <Expr as FromStr>::Err> {
+        parse::expression(s, &Limits::default())
+    }
+}
+
+/// The outcome of evaluating an `Expr`.
+///
+/// Mirrors the shape of the expression it came from, keeping every
+/// individual roll so that the dice can be shown next to the total.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum ExprResult {
+    Roll(RollResult),
+    Pool(PoolResult),
+    Constant(i64),
+    Binary(Op, Box<ExprResult>, Box<ExprResult>),
+}
+
+impl ExprResult {
+    /// Returns the value of the whole expression.
+    ///
+    /// # Panics
+    /// Panics if the total overflows, which `Expr::result` rules out for
+    /// every result it returns; see `checked_total`.
+    pub fn total(&self) -> i128 {
+        self.checked_total().expect("total of an expression overflowed")
+    }
+
+    /// Returns the value of the whole expression, or None if it overflows.
+    pub fn checked_total(&self) -> Option<i128> {
+        match *self {
+            ExprResult::Roll(ref roll) => Some(roll.total()),
+            ExprResult::Pool(ref pool) => Some(i128::from(pool.total())),
+            ExprResult::Constant(n) => Some(i128::from(n)),
+            ExprResult::Binary(op, ref lhs, ref rhs) => {
+                op.apply(lhs.checked_total()?, rhs.checked_total()?)
+            }
+        }
+    }
+
+    /// Returns every roll in the expression from left to right, including
+    /// the dice of pools.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::Expr;
+    ///
+    /// let expr: Expr = "2d20kh1 + 1d4 + 3".parse().unwrap();
+    /// let result = expr.result(&mut |max| max).unwrap();
+    /// let kept: Vec<_> = result.rolls().iter().map(|roll| roll.kept()).collect();
+    /// assert!(kept == vec![vec![20], vec![4]]);
+    /// ```
+    pub fn rolls(&self) -> Vec<&RollResult> {
+        match *self {
+            ExprResult::Roll(ref roll) => vec![roll],
+            ExprResult::Pool(ref pool) => vec![pool.roll()],
+            ExprResult::Constant(_) => Vec::new(),
+            ExprResult::Binary(_, ref lhs, ref rhs) => {
+                let mut rolls = lhs.rolls();
+                rolls.extend(rhs.rolls());
+                rolls
+            }
+        }
+    }
+
+    fn fmt_terms(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExprResult::Roll(ref roll) => fmt_dice(roll, f),
+            ExprResult::Pool(ref pool) => fmt_dice(pool.roll(), f),
+            ExprResult::Constant(n) => write!(f, "{}", n),
+            ExprResult::Binary(op, ref lhs, ref rhs) => {
+                lhs.fmt_operand(f, op, false)?;
+                write!(f, " {} ", op)?;
+                rhs.fmt_operand(f, op, true)
+            }
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parent: Op, right: bool) -> fmt::Result {
+        match *self {
+            ExprResult::Binary(op, _, _) if parent.needs_parens(op, right) => {
+                write!(f, "(")?;
+                self.fmt_terms(f)?;
+                write!(f, ")")
+            }
+            _ => self.fmt_terms(f),
+        }
+    }
+}
+
+/// Lists the dice of a roll in brackets, without its total.
+fn fmt_dice(roll: &RollResult, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "[")?;
+    roll.fmt_dice(f)?;
+    write!(f, "]")
+}
+
+impl fmt::Display for ExprResult {
+    /// Implements Display for ExprResult.
+    ///
+    /// A lone roll or pool is displayed exactly like a `RollResult` or
+    /// `PoolResult`, anything more complex lists the dice of each roll in
+    /// brackets.
+    ///
+    /// # Examples
+    /// [1, 2] + 3 => "[1, 2] + 3 (6)"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExprResult::Roll(ref roll) => write!(f, "{}", roll),
+            ExprResult::Pool(ref pool) => write!(f, "{}", pool),
+            _ => {
+                self.fmt_terms(f)?;
+                write!(f, " ({})", self.total())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod expr_test {
+    use super::*;
+    use RollCommand;
+
+    fn roll(count: u32, range: u32) -> Box<Expr> {
+        Box::new(Expr::Roll(RollCommand::new(count, range)))
+    }
+
+    #[test]
+    fn can_parse_sums_of_rolls() {
+        let expr = Expr::Binary(Op::Add, roll(2, 6), Box::new(Expr::Constant(3)));
+        assert_eq!(expr, "2d6 + 3".parse().unwrap());
+    }
+
+    #[test]
+    fn multiplication_binds_tighter() {
+        let expr = Expr::Binary(
+            Op::Add,
+            roll(1, 4),
+            Box::new(Expr::Binary(Op::Mul, Box::new(Expr::Constant(2)), roll(1, 6))),
+        );
+        assert_eq!(expr, "1d4+2*d6".parse().unwrap());
+    }
+
+    #[test]
+    fn parentheses_group_subexpressions() {
+        let expr: Expr = "(1d8 + 2) * 2".parse().unwrap();
+        assert_eq!(20, expr.result(&mut |max| max).unwrap().total());
+    }
+
+    #[test]
+    fn subtraction_can_go_negative() {
+        let expr: Expr = "4d6 - 1d6 - 30".parse().unwrap();
+        let result = expr.result(&mut |_| 1).unwrap();
+        assert_eq!(-27, result.total());
+        assert_eq!("[1, 1, 1, 1] - [1] - 30 (-27)", result.to_string());
+    }
+
+    #[test]
+    fn pools_count_successes_in_expressions() {
+        let expr: Expr = "6d6=6 + 1".parse().unwrap();
+        let result = expr.result(&mut |max| max).unwrap();
+        assert_eq!(7, result.total());
+        assert_eq!("[6, 6, 6, 6, 6, 6] + 1 (7)", result.to_string());
+    }
+
+    #[test]
+    fn totals_go_beyond_u32() {
+        let limits = Limits::default().dice(100_000).total_dice(100_000).sides(100_000);
+        let expr = Expr::parse_with("100000d100000", &limits).unwrap();
+        let result = expr.result_with(&mut |max| max, &limits).unwrap();
+        assert_eq!(10_000_000_000, result.total());
+    }
+
+    #[test]
+    fn totals_go_beyond_i64() {
+        let max = i128::from(i64::MAX);
+        let expr: Expr = "2d{9223372036854775807}".parse().unwrap();
+        assert_eq!(2 * max, expr.result(&mut |max| max).unwrap().total());
+        let expr: Expr = "d{9223372036854775807} * 9223372036854775807".parse().unwrap();
+        assert_eq!(max * max, expr.result(&mut |max| max).unwrap().total());
+        let expr: Expr = "d{-9223372036854775807} - 9223372036854775807".parse().unwrap();
+        assert_eq!(-2 * max, expr.result(&mut |max| max).unwrap().total());
+    }
+
+    #[test]
+    fn overflowing_totals_are_errors() {
+        let expr: Expr = "d{9223372036854775807} * 9223372036854775807 * 4".parse().unwrap();
+        assert_eq!(Some(RollError::Overflow), expr.result(&mut |max| max).err());
+        assert!("d{9223372036854775808}".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn lone_numbers_are_dice() {
+        assert_eq!(Expr::Roll(RollCommand::new(1, 6)), "6".parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for s in &["", "2d6 +", "(2d6", "2d6)", "2d 6", "2d6 x 3"] {
+            assert!(s.parse::<Expr>().is_err(), "{:?} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! The faces printed on a die.
+
+use std::fmt;
+
+use error::RollError;
+
+/// The faces printed on each die of a roll.
+///
+/// The function passed to `RollCommand::result` picks a side between 1 and
+/// `sides()`, which is then turned into the value printed on that side.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Faces {
+    /// Faces numbered from 1 up to and including the given number: d6, d%
+    Numbered(u32),
+    /// Fate or Fudge dice, showing -1, 0 or +1: dF
+    Fate,
+    /// Any list of numbers, one per side: d{1,1,2,2,3,4}
+    Custom(Vec<i64>),
+    /// Named symbols instead of numbers, one per side: d{hit,hit,miss,crit}
+    ///
+    /// The value of a symbol is the side it is printed on, but symbols do
+    /// not add up to a total; they are tallied instead.
+    Symbols(Vec<String>),
+}
+
+impl Faces {
+    /// Returns the number of distinct sides to pick from.
+    pub fn sides(&self) -> u32 {
+        match *self {
+            Faces::Numbered(range) => range,
+            Faces::Fate => 3,
+            Faces::Custom(ref values) => values.len() as u32,
+            Faces::Symbols(ref symbols) => symbols.len() as u32,
+        }
+    }
+
+    /// Returns the value printed on a side, counting sides from 1, or None
+    /// if the die has no such side.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::Faces;
+    ///
+    /// assert!(Faces::Fate.value(3) == Some(1));
+    /// assert!(Faces::Custom(vec![2, 4]).value(3) == None);
+    /// ```
+    pub fn value(&self, side: u32) -> Option<i64> {
+        if side == 0 || side > self.sides() {
+            return None;
+        }
+        Some(match *self {
+            Faces::Numbered(_) | Faces::Symbols(_) => i64::from(side),
+            Faces::Fate => i64::from(side) - 2,
+            Faces::Custom(ref values) => values[side as usize - 1],
+        })
+    }
+
+    /// Returns the highest value on the die, or 0 if it has no sides.
+    pub fn highest(&self) -> i64 {
+        match *self {
+            Faces::Custom(ref values) => values.iter().cloned().max().unwrap_or(0),
+            _ => self.value(self.sides()).unwrap_or(0),
+        }
+    }
+
+    /// Returns true if the faces are symbols rather than numbers.
+    pub fn is_symbolic(&self) -> bool {
+        matches!(*self, Faces::Symbols(_))
+    }
+
+    /// Rolls one die, using `pick` to pick the side.
+    pub(crate) fn roll<P>(&self, pick: &mut P) -> Result<i64, RollError>
+    where
+        P: FnMut(u32) -> Result<u32, RollError>,
+    {
+        let sides = self.sides();
+        let side = pick(sides)?;
+        self.value(side).ok_or(RollError::FaceOutOfRange { side, sides })
+    }
+
+    /// Renders a value the way it is printed on the die.
+    ///
+    /// Fate dice show symbols instead of numbers: -1, 0, 1 => "-", "0", "+"
+    /// Symbol dice show the symbol on that side: 2 on d{hit,miss} => "miss"
+    pub fn label(&self, value: i64) -> String {
+        match *self {
+            Faces::Fate if value < 0 => "-".to_string(),
+            Faces::Fate if value > 0 => "+".to_string(),
+            Faces::Symbols(ref symbols) if value >= 1 && value <= symbols.len() as i64 => {
+                symbols[value as usize - 1].clone()
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Faces {
+    /// Writes the faces the way they follow the 'd' of a die.
+    ///
+    /// # Examples
+    /// Numbered(6) => "6", Fate => "F", Custom([1, -1]) => "{1,-1}"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let labels: Vec<String> = match *self {
+            Faces::Numbered(range) => return write!(f, "{}", range),
+            Faces::Fate => return f.write_str("F"),
+            Faces::Custom(ref values) => values.iter().map(|v| v.to_string()).collect(),
+            Faces::Symbols(ref symbols) => symbols.clone(),
+        };
+        write!(f, "{{{}}}", labels.join(","))
+    }
+}
@@ -0,0 +1,351 @@
+//! Provably fair rolls through a commit-reveal scheme.
+//!
+//! The roller picks a secret server seed and publishes its SHA-256 hash,
+//! the commitment, before the player chooses a client seed. Every roll then
+//! takes its sides from HMAC-SHA256 keyed with the server seed over the
+//! message `"{client seed}:{nonce}:{round}"`, where the nonce counts the
+//! rolls made with the pair of seeds and the round counts the HMAC blocks
+//! used by one roll. Each block gives eight big-endian 32-bit words, which
+//! are turned into sides with `sample_side`.
+//!
+//! The roller cannot pick a server seed to suit the client seed, since the
+//! commitment is already out, and the player cannot predict the rolls
+//! without the server seed. Once the server seed is revealed, anyone can
+//! check it against the commitment and roll every result again.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use rand::{OsRng, Rng};
+use sha2::{Digest, Sha256};
+
+use error::{FairError, RollError};
+use source::{sample_side, DieSource};
+use {Expr, ExprResult};
+
+/// Reads 32 bytes written as 64 hex digits.
+fn from_hex(s: &str) -> Result<[u8; 32], FairError> {
+    let s = s.trim();
+    if s.len() != 64 || !s.is_ascii() {
+        return Err(FairError::Malformed);
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| FairError::Malformed)?;
+    }
+    Ok(bytes)
+}
+
+fn write_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// The secret seed of the roller, kept hidden until the rolls are done.
+///
+/// Displays and parses as 64 hex digits.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerSeed([u8; 32]);
+
+impl ServerSeed {
+    /// Generates a new server seed with randomness from the operating
+    /// system.
+    pub fn generate() -> io::Result<ServerSeed> {
+        let mut bytes = [0; 32];
+        OsRng::new()?.fill_bytes(&mut bytes);
+        Ok(ServerSeed(bytes))
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> ServerSeed {
+        ServerSeed(bytes)
+    }
+
+    /// Returns the commitment to publish before the client seed is chosen.
+    pub fn commitment(&self) -> Commitment {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(self.0));
+        Commitment(hash)
+    }
+}
+
+impl fmt::Display for ServerSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+impl FromStr for ServerSeed {
+    type Err = FairError;
+
+    fn from_str(s: &str) -> Result<ServerSeed, FairError> {
+        from_hex(s).map(ServerSeed)
+    }
+}
+
+/// The SHA-256 hash of a server seed.
+///
+/// Displays and parses as 64 hex digits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Commitment([u8; 32]);
+
+impl Commitment {
+    /// Returns true if `seed` is the server seed committed to.
+    pub fn matches(&self, seed: &ServerSeed) -> bool {
+        *self == seed.commitment()
+    }
+}
+
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+impl FromStr for Commitment {
+    type Err = FairError;
+
+    fn from_str(s: &str) -> Result<Commitment, FairError> {
+        from_hex(s).map(Commitment)
+    }
+}
+
+/// Picks the sides of a single roll from a server seed, a client seed and
+/// a nonce, as described in the module documentation.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Expr, FairSource, ServerSeed};
+///
+/// let seed = ServerSeed::from_bytes([7; 32]);
+/// let expr: Expr = "4d6kh3".parse().unwrap();
+/// let first = expr.result(&mut FairSource::new(&seed, "player", 0)).unwrap();
+/// let again = expr.result(&mut FairSource::new(&seed, "player", 0)).unwrap();
+/// assert!(first.to_string() == again.to_string());
+/// ```
+pub struct FairSource {
+    mac: Hmac<Sha256>,
+    client_seed: String,
+    nonce: u64,
+    round: u64,
+    words: Vec<u32>,
+}
+
+impl FairSource {
+    pub fn new(server_seed: &ServerSeed, client_seed: &str, nonce: u64) -> FairSource {
+        let mac = Hmac::new_from_slice(&server_seed.0).expect("HMAC takes keys of any length");
+        let client_seed = client_seed.to_string();
+        FairSource { mac, client_seed, nonce, round: 0, words: Vec::new() }
+    }
+
+    fn next_word(&mut self) -> u32 {
+        if self.words.is_empty() {
+            let mut mac = self.mac.clone();
+            mac.update(format!("{}:{}:{}", self.client_seed, self.nonce, self.round).as_bytes());
+            let block = mac.finalize().into_bytes();
+            // stored in reverse, so that popping takes the words in order
+            self.words = block
+                .chunks(4)
+                .rev()
+                .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+                .collect();
+            self.round += 1;
+        }
+        self.words.pop().expect("a block holds eight words")
+    }
+}
+
+impl DieSource for FairSource {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        Ok(sample_side(sides, &mut || self.next_word()))
+    }
+}
+
+/// Rolls provably fair results for one player.
+///
+/// Generate a `ServerSeed` and publish its commitment first, then take the
+/// client seed from the player. Every roll uses the next nonce, starting
+/// from 0. Revealing the server seed at the end lets the player `verify`
+/// each roll.
+///
+/// # Examples
+/// ```
+/// use rcmd::{fair, Expr, FairRoller, ServerSeed};
+///
+/// let seed = ServerSeed::generate().unwrap();
+/// let commitment = seed.commitment();
+///
+/// let mut roller = FairRoller::new(seed, "lucky dice");
+/// let expr: Expr = "1d20 + 5".parse().unwrap();
+/// let (nonce, result) = roller.roll(&expr).unwrap();
+///
+/// let seed = roller.reveal();
+/// let verified = fair::verify(&commitment, &seed, "lucky dice", nonce, &expr).unwrap();
+/// assert!(verified.to_string() == result.to_string());
+/// ```
+pub struct FairRoller {
+    server_seed: ServerSeed,
+    client_seed: String,
+    nonce: u64,
+}
+
+impl FairRoller {
+    pub fn new(server_seed: ServerSeed, client_seed: &str) -> FairRoller {
+        FairRoller { server_seed, client_seed: client_seed.to_string(), nonce: 0 }
+    }
+
+    /// Returns this roller starting from `nonce` instead of 0, to carry on
+    /// a series of rolls made with the same seeds.
+    pub fn nonce(self, nonce: u64) -> FairRoller {
+        FairRoller { nonce, ..self }
+    }
+
+    pub fn commitment(&self) -> Commitment {
+        self.server_seed.commitment()
+    }
+
+    /// Rolls `expr` with the next nonce, returning the nonce along with the
+    /// result.
+    pub fn roll(&mut self, expr: &Expr) -> Result<(u64, ExprResult), RollError> {
+        let (nonce, mut source) = self.next_source();
+        Ok((nonce, expr.result(&mut source)?))
+    }
+
+    /// Takes the next nonce, returning it along with the source for it, to
+    /// roll anything else than a single `Expr`, such as a `Repeat`.
+    pub fn next_source(&mut self) -> (u64, FairSource) {
+        let nonce = self.nonce;
+        self.nonce += 1;
+        (nonce, FairSource::new(&self.server_seed, &self.client_seed, nonce))
+    }
+
+    /// Ends the series of rolls, giving away the server seed.
+    pub fn reveal(self) -> ServerSeed {
+        self.server_seed
+    }
+}
+
+/// Rolls `expr` again as it was rolled by a `FairRoller`, after checking
+/// the revealed server seed against the commitment.
+///
+/// # Examples
+/// ```
+/// use rcmd::{fair, Expr, FairError, ServerSeed};
+///
+/// let commitment = ServerSeed::from_bytes([1; 32]).commitment();
+/// let other = ServerSeed::from_bytes([2; 32]);
+/// let expr: Expr = "1d20".parse().unwrap();
+/// let err = fair::verify(&commitment, &other, "player", 0, &expr).err();
+/// assert!(Some(FairError::SeedMismatch) == err);
+/// ```
+pub fn verify(
+    commitment: &Commitment,
+    server_seed: &ServerSeed,
+    client_seed: &str,
+    nonce: u64,
+    expr: &Expr,
+) -> Result<ExprResult, FairError> {
+    let mut source = verified_source(commitment, server_seed, client_seed, nonce)?;
+    expr.result(&mut source).map_err(FairError::Roll)
+}
+
+/// Returns the source a `FairRoller` rolled with for `nonce`, after
+/// checking the revealed server seed against the commitment, to verify
+/// anything else than a single `Expr`.
+///
+/// # Examples
+/// ```
+/// use rcmd::{fair, FairRoller, Repeat, ServerSeed};
+///
+/// let seed = ServerSeed::from_bytes([3; 32]);
+/// let commitment = seed.commitment();
+/// let scores: Repeat = "6x4d6kh3".parse().unwrap();
+///
+/// let mut roller = FairRoller::new(seed, "player");
+/// let (nonce, mut source) = roller.next_source();
+/// let rolled = scores.result(&mut source).unwrap();
+///
+/// let seed = roller.reveal();
+/// let mut source = fair::verified_source(&commitment, &seed, "player", nonce).unwrap();
+/// assert!(scores.result(&mut source).unwrap().totals() == rolled.totals());
+/// ```
+pub fn verified_source(
+    commitment: &Commitment,
+    server_seed: &ServerSeed,
+    client_seed: &str,
+    nonce: u64,
+) -> Result<FairSource, FairError> {
+    if !commitment.matches(server_seed) {
+        return Err(FairError::SeedMismatch);
+    }
+    Ok(FairSource::new(server_seed, client_seed, nonce))
+}
+
+#[cfg(test)]
+mod fair_test {
+    use super::*;
+
+    #[test]
+    fn commitments_are_sha256() {
+        let commitment = ServerSeed::from_bytes([0; 32]).commitment();
+        let hash = "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925";
+        assert_eq!(hash, commitment.to_string());
+        assert_eq!(Ok(commitment), hash.parse());
+    }
+
+    #[test]
+    fn seeds_survive_display() {
+        let seed = ServerSeed::generate().unwrap();
+        assert_eq!(Ok(seed.clone()), seed.to_string().parse());
+        assert!(seed != ServerSeed::generate().unwrap());
+
+        assert_eq!(Err(FairError::Malformed), "abc".parse::<ServerSeed>());
+        assert_eq!(Err(FairError::Malformed), "g".repeat(64).parse::<Commitment>());
+    }
+
+    #[test]
+    fn sides_come_from_hmac() {
+        // HMAC-SHA256("\x2a" * 32, "player:3:0") starts with 0xc13f1f3e,
+        // and 0xc13f1f3e % 20 + 1 == 15
+        let seed = ServerSeed::from_bytes([42; 32]);
+        let mut source = FairSource::new(&seed, "player", 3);
+        assert_eq!(Ok(15), source.pick(20));
+    }
+
+    #[test]
+    fn rolls_depend_on_every_input() {
+        let picks = |seed: u8, client_seed: &str, nonce| {
+            let seed = ServerSeed::from_bytes([seed; 32]);
+            let mut source = FairSource::new(&seed, client_seed, nonce);
+            (0..20).map(|_| source.pick(1000).unwrap()).collect::<Vec<_>>()
+        };
+        let first = picks(1, "player", 0);
+        assert_eq!(first, picks(1, "player", 0));
+        assert!(first != picks(2, "player", 0));
+        assert!(first != picks(1, "players", 0));
+        assert!(first != picks(1, "player", 1));
+    }
+
+    #[test]
+    fn verify_reproduces_rolls() {
+        let seed = ServerSeed::from_bytes([5; 32]);
+        let commitment = seed.commitment();
+        let exprs: Vec<Expr> = vec!["4d6kh3".parse().unwrap(), "8d10!>=9 + 3".parse().unwrap()];
+
+        let mut roller = FairRoller::new(seed, "client").nonce(10);
+        let mut rolls = Vec::new();
+        for expr in &exprs {
+            rolls.push(roller.roll(expr).unwrap());
+        }
+        assert_eq!(vec![10, 11], rolls.iter().map(|&(nonce, _)| nonce).collect::<Vec<_>>());
+
+        let seed = roller.reveal();
+        for (expr, &(nonce, ref result)) in exprs.iter().zip(&rolls) {
+            let verified = verify(&commitment, &seed, "client", nonce, expr).unwrap();
+            assert_eq!(result.to_string(), verified.to_string());
+        }
+    }
+}
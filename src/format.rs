@@ -0,0 +1,246 @@
+//! The formats `dice` prints its rolls in, for people and for other tools.
+
+use rcmd::ExprResult;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json;
+
+/// How the results of rolls are printed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// As displayed by `ExprResult`: "1, 2, 3 (6)"
+    Plain,
+    /// A single JSON array holding a record for every roll, with the
+    /// result as serialized by the `serde` feature of the library.
+    Json,
+    /// A line of comma-separated values for every die, after a header.
+    Csv,
+    /// A JSON record on its own line for every roll.
+    Ndjson,
+}
+
+/// Writes `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings always serialize")
+}
+
+/// Writes `s` as a CSV field, quoted since expressions may hold commas.
+pub fn csv_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// What is printed of a single die in CSV: the dice term of the
+/// expression it was rolled in, counting from 1, its face and the faces it
+/// exploded into or rerolled away, all as labelled on the die, and its value
+/// unless it shows symbols.
+struct DieRecord {
+    roll: usize,
+    face: String,
+    explosions: Vec<String>,
+    rerolled: Vec<String>,
+    value: Option<i128>,
+    kept: bool,
+}
+
+/// What is printed of a single roll.
+///
+/// The seed is the one given with `--seed`, and the nonce the one of a
+/// provably fair roll. In JSON the result is written the way the library
+/// serializes an `ExprResult`, so the dice, pool counts and symbol tallies
+/// follow the same schema as for any other user of the library.
+pub struct Record<'a> {
+    pub expression: String,
+    pub result: &'a ExprResult,
+    pub seed: Option<u64>,
+    pub nonce: Option<u64>,
+}
+
+/// Serializes the expression and its result along with the total, so that
+/// it does not have to be added up from the result.
+impl<'a> Serialize for Record<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Record", 5)?;
+        state.serialize_field("expression", &self.expression)?;
+        state.serialize_field("result", self.result)?;
+        state.serialize_field("total", &self.result.total())?;
+        state.serialize_field("seed", &self.seed)?;
+        state.serialize_field("nonce", &self.nonce)?;
+        state.end()
+    }
+}
+
+impl<'a> Record<'a> {
+    fn dice(&self) -> Vec<DieRecord> {
+        let mut dice = Vec::new();
+        for (i, roll) in self.result.rolls().into_iter().enumerate() {
+            let labels = |faces: &[i64]| faces.iter().map(|&n| roll.faces().label(n)).collect();
+            dice.extend(roll.iter().map(|die| DieRecord {
+                roll: i + 1,
+                face: roll.faces().label(die.face()),
+                explosions: labels(die.explosions()),
+                rerolled: labels(die.rerolled()),
+                value: Some(die.value()).filter(|_| !roll.faces().is_symbolic()),
+                kept: die.is_kept(),
+            }));
+        }
+        dice
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("results always serialize")
+    }
+
+    /// Writes a line for every die, or a single line without any die for
+    /// an expression without dice.
+    fn to_csv(&self) -> Vec<String> {
+        let number = |n: Option<u64>| n.map_or(String::new(), |n| n.to_string());
+        let line = |die: String| {
+            format!(
+                "{},{},{},{},{}",
+                csv_string(&self.expression),
+                die,
+                self.result.total(),
+                number(self.seed),
+                number(self.nonce)
+            )
+        };
+        let dice = self.dice();
+        if dice.is_empty() {
+            return vec![line(",,,,,".to_string())];
+        }
+        dice.iter()
+            .map(|die| {
+                line(format!(
+                    "{},{},{},{},{},{}",
+                    die.roll,
+                    csv_string(&die.face),
+                    csv_string(&die.explosions.join(" ")),
+                    csv_string(&die.rerolled.join(" ")),
+                    die.value.map_or(String::new(), |n| n.to_string()),
+                    die.kept
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Prints records in one format, keeping track of what surrounds them.
+pub struct Printer {
+    format: Format,
+    printed: usize,
+    finished: bool,
+}
+
+impl Printer {
+    pub fn new(format: Format) -> Printer {
+        Printer { format, printed: 0, finished: false }
+    }
+
+    pub fn print(&mut self, record: &Record) {
+        match self.format {
+            Format::Plain => match record.nonce {
+                Some(nonce) => println!("nonce {}: {}", nonce, record.result),
+                None => println!("{}", record.result),
+            },
+            Format::Json if self.printed == 0 => print!("[{}", record.to_json()),
+            Format::Json => print!(",\n{}", record.to_json()),
+            Format::Csv => {
+                if self.printed == 0 {
+                    println!(
+                        "expression,roll,face,explosions,rerolled,value,kept,total,seed,nonce"
+                    );
+                }
+                for line in record.to_csv() {
+                    println!("{}", line);
+                }
+            }
+            Format::Ndjson => println!("{}", record.to_json()),
+        }
+        self.printed += 1;
+    }
+
+    /// Closes the JSON array, which is empty if nothing was printed. Does
+    /// nothing when called again.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        match self.format {
+            Format::Json if self.printed == 0 => println!("[]"),
+            Format::Json => println!("]"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod format_test {
+    use super::*;
+    use rcmd::Expr;
+
+    fn record<'a>(s: &str, result: &'a ExprResult) -> Record<'a> {
+        Record { expression: s.to_string(), result, seed: Some(42), nonce: None }
+    }
+
+    fn roll(s: &str, sides: &[u32]) -> ExprResult {
+        let expr: Expr = s.parse().unwrap();
+        let mut sides = sides.iter();
+        expr.result(&mut |_| *sides.next().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn records_list_every_die() {
+        let result = roll("2d20kh1 + 5", &[16, 16]);
+        let advantage = record("2d20kh1 + 5", &result);
+        let json: serde_json::Value = serde_json::from_str(&advantage.to_json()).unwrap();
+        assert_eq!("2d20kh1 + 5", json["expression"]);
+        assert_eq!(21, json["total"]);
+        assert_eq!(42, json["seed"]);
+        assert!(json["nonce"].is_null());
+        let dice = &json["result"]["Binary"][1]["Roll"]["dice"];
+        assert_eq!((true, false), (dice[0]["kept"] == true, dice[1]["kept"] == true));
+
+        let csv = vec![
+            "\"2d20kh1 + 5\",1,\"16\",\"\",\"\",16,true,21,42,",
+            "\"2d20kh1 + 5\",1,\"16\",\"\",\"\",16,false,21,42,",
+        ];
+        assert_eq!(csv, advantage.to_csv());
+
+        let result = roll("3 + 4", &[]);
+        assert_eq!(vec!["\"3 + 4\",,,,,,,7,42,"], record("3 + 4", &result).to_csv());
+    }
+
+    #[test]
+    fn json_keeps_pool_counts_and_tallies() {
+        let result = roll("4d10>=7f1 + 2d{hit,miss}", &[10, 1, 7, 3, 2, 2]);
+        let json = record("", &result).to_json();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let pool = &json["result"]["Binary"][1]["Pool"];
+        assert_eq!(2, pool["successes"]);
+        assert_eq!(1, pool["failures"]);
+        assert_eq!(1, pool["total"]);
+        let symbols = &json["result"]["Binary"][2]["Roll"];
+        assert_eq!(serde_json::json!([["miss", 2]]), symbols["tally"]);
+        assert_eq!(1, json["total"]);
+    }
+
+    #[test]
+    fn dice_keep_explosions_rerolls_and_labels() {
+        let result = roll("2d6!r1 + 3d{hit,miss}", &[6, 3, 1, 2, 2, 2, 1]);
+        let dice = record("", &result).dice();
+        assert_eq!(5, dice.len());
+        assert_eq!(("6", Some(9), true), (dice[0].face.as_str(), dice[0].value, dice[0].kept));
+        assert_eq!(vec!["3".to_string()], dice[0].explosions);
+        assert_eq!("2", dice[1].face);
+        assert_eq!(vec!["1".to_string()], dice[1].rerolled);
+        let faces: Vec<_> = dice[2..].iter().map(|die| (die.roll, die.face.as_str())).collect();
+        assert_eq!(vec![(2, "miss"), (2, "miss"), (2, "hit")], faces);
+        assert_eq!(None, dice[2].value);
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!("\"say \\\"hi\\\"\\n\"", json_string("say \"hi\"\n"));
+        assert_eq!("\"3d{a,\"\"b\"\"}\"", csv_string("3d{a,\"b\"}"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Tokenizer for roll commands and roll expressions.
+
+use std::ops::Range;
+
+use error::ParseError;
+
+/// Keywords of the dice notation, longest first so that `kh` wins over `k`.
+const WORDS: &[&str] = &[
+    "repeat", "ro", "kh", "kl", "dh", "dl", "d", "k", "r", "f", "F", "p", "x",
+];
+
+/// Operators and delimiters, longest first so that `>=` wins over `>`.
+const PUNCTS: &[&str] = &[
+    "<=", ">=", "!!", "!", "<", ">", "=", "+", "-", "*", "(", ")", "{", "}", ",", "%",
+];
+
+/// The different kinds of tokens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenKind<'a> {
+    /// A run of ascii digits.
+    Number(&'a str),
+    /// A keyword of the dice notation, such as `d`, `kh` or `ro`.
+    Word(&'static str),
+    /// A face inside braces, such as `hit` or `12`.
+    Label(&'a str),
+    /// An operator or delimiter, such as `+`, `>=` or `{`.
+    Punct(&'static str),
+}
+
+/// A token along with where it was found in the input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Range<usize>,
+    /// Whether whitespace separates this token from the one before it.
+    pub spaced: bool,
+}
+
+/// Splits `src` into tokens, failing on the first character that does not
+/// start any token.
+///
+/// Inside braces runs of letters, digits and underscores are face labels;
+/// everywhere else letters must spell out one of the keywords.
+pub fn tokenize(src: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut spaced = false;
+    let mut in_braces = false;
+
+    while let Some(c) = src[pos..].chars().next() {
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            spaced = true;
+            continue;
+        }
+
+        let rest = &src[pos..];
+        let is_label = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let kind = if in_braces && is_label(c) {
+            TokenKind::Label(&rest[..rest.find(|c| !is_label(c)).unwrap_or(rest.len())])
+        } else if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            TokenKind::Number(&rest[..len])
+        } else if let Some(word) = WORDS.iter().find(|w| rest.starts_with(*w)) {
+            TokenKind::Word(word)
+        } else if let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(*p)) {
+            TokenKind::Punct(punct)
+        } else {
+            let span = pos..pos + c.len_utf8();
+            return Err(ParseError::UnexpectedChar { found: c, span });
+        };
+
+        let len = match kind {
+            TokenKind::Number(s) | TokenKind::Label(s) => s.len(),
+            TokenKind::Word(s) | TokenKind::Punct(s) => s.len(),
+        };
+        match kind {
+            TokenKind::Punct("{") => in_braces = true,
+            TokenKind::Punct("}") => in_braces = false,
+            _ => {}
+        }
+        tokens.push(Token { kind, span: pos..pos + len, spaced });
+        pos += len;
+        spaced = false;
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod lex_test {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind<'_>> {
+        tokenize(src).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn splits_modifiers_into_keywords() {
+        use self::TokenKind::*;
+        let expected = vec![
+            Number("4"), Word("d"), Word("F"), Punct("!"), Word("p"),
+            Word("kh"), Number("3"), Punct(">="), Number("1"),
+        ];
+        assert_eq!(expected, kinds("4dF!pkh3>=1"));
+    }
+
+    #[test]
+    fn braces_hold_labels() {
+        use self::TokenKind::*;
+        let expected = vec![
+            Word("d"), Punct("{"), Label("hit"), Punct(","), Punct("-"), Label("1"), Punct("}"),
+            Word("kh"), Number("1"),
+        ];
+        assert_eq!(expected, kinds("d{hit,-1}kh1"));
+    }
+
+    #[test]
+    fn repeats_are_keywords() {
+        use self::TokenKind::*;
+        let expected = vec![
+            Number("6"), Word("x"), Word("d"), Number("6"), Word("ro"), Number("1"),
+        ];
+        assert_eq!(expected, kinds("6xd6ro1"));
+        let expected = vec![
+            Word("repeat"), Punct("("), Number("2"), Punct(","), Word("d"), Number("4"),
+        ];
+        assert_eq!(expected, kinds("repeat(2,d4"));
+    }
+
+    #[test]
+    fn remembers_whitespace() {
+        let tokens = tokenize("2d6 + 3").unwrap();
+        let spaced: Vec<_> = tokens.iter().map(|t| t.spaced).collect();
+        assert_eq!(vec![false, false, false, true, true], spaced);
+    }
+
+    #[test]
+    fn rejects_unknown_letters() {
+        let err = tokenize("foo6").unwrap_err();
+        assert_eq!(ParseError::UnexpectedChar { found: 'o', span: 1..2 }, err);
+    }
+}
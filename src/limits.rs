@@ -0,0 +1,222 @@
+//! Resource limits for parsing and rolling untrusted roll expressions.
+
+use std::fmt;
+
+use error::RollError;
+use modifier::DEFAULT_EXPLOSION_DEPTH;
+
+/// Caps the work a single roll expression may cause.
+///
+/// The parser rejects expressions that are too large up front, and the
+/// evaluator stops as soon as a roll goes over budget, so that input like
+/// `4000000000d6` fails with an error instead of running out of memory.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Expr, Limits};
+///
+/// let limits = Limits::default().dice(10);
+/// assert!(Expr::parse_with("10d6", &limits).is_ok());
+/// assert!(Expr::parse_with("11d6", &limits).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    pub(crate) dice: u32,
+    pub(crate) total_dice: u64,
+    pub(crate) sides: u32,
+    pub(crate) explosion_depth: u32,
+    pub(crate) nodes: usize,
+    pub(crate) steps: u64,
+    pub(crate) chances: u64,
+}
+
+impl Limits {
+    /// Returns these limits allowing at most `n` dice in a single term.
+    pub fn dice(self, n: u32) -> Limits {
+        Limits { dice: n, ..self }
+    }
+
+    /// Returns these limits allowing at most `n` dice in a whole expression.
+    pub fn total_dice(self, n: u64) -> Limits {
+        Limits { total_dice: n, ..self }
+    }
+
+    /// Returns these limits allowing dice with at most `n` sides.
+    pub fn sides(self, n: u32) -> Limits {
+        Limits { sides: n, ..self }
+    }
+
+    /// Returns these limits allowing at most `n` explosions per die.
+    pub fn explosion_depth(self, n: u32) -> Limits {
+        Limits { explosion_depth: n, ..self }
+    }
+
+    /// Returns these limits allowing at most `n` terms, operators and
+    /// parentheses in an expression.
+    pub fn nodes(self, n: usize) -> Limits {
+        Limits { nodes: n, ..self }
+    }
+
+    /// Returns these limits allowing at most `n` evaluation steps, where
+    /// every side picked and every operator applied is one step.
+    pub fn steps(self, n: u64) -> Limits {
+        Limits { steps: n, ..self }
+    }
+
+    /// Returns these limits allowing at most `n` chances to be computed for
+    /// a `Distribution`, where every chance of a total and every product of
+    /// two chances summed into one counts.
+    pub fn chances(self, n: u64) -> Limits {
+        Limits { chances: n, ..self }
+    }
+}
+
+impl Default for Limits {
+    /// Limits generous enough for any table, but small enough to evaluate
+    /// in well under a second.
+    fn default() -> Limits {
+        Limits {
+            dice: 1_000,
+            total_dice: 10_000,
+            sides: 1_000_000,
+            explosion_depth: DEFAULT_EXPLOSION_DEPTH,
+            nodes: 256,
+            steps: 1_000_000,
+            chances: 100_000_000,
+        }
+    }
+}
+
+/// A limit that was exceeded, along with its configured maximum.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    Dice(u32),
+    TotalDice(u64),
+    Sides(u32),
+    ExplosionDepth(u32),
+    Nodes(usize),
+    Steps(u64),
+    Chances(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Limit::Dice(n) => write!(f, "more than {} dice in one term", n),
+            Limit::TotalDice(n) => write!(f, "more than {} dice in total", n),
+            Limit::Sides(n) => write!(f, "more than {} sides", n),
+            Limit::ExplosionDepth(n) => write!(f, "more than {} explosions on one die", n),
+            Limit::Nodes(n) => write!(f, "more than {} terms and operators", n),
+            Limit::Steps(n) => write!(f, "more than {} steps", n),
+            Limit::Chances(n) => write!(f, "more than {} chances in a distribution", n),
+        }
+    }
+}
+
+/// Keeps track of the work done while evaluating one expression.
+pub(crate) struct Budget<'a> {
+    pub(crate) limits: &'a Limits,
+    dice: u64,
+    steps: u64,
+    chances: u64,
+}
+
+impl<'a> Budget<'a> {
+    pub(crate) fn new(limits: &'a Limits) -> Budget<'a> {
+        Budget { limits, dice: 0, steps: 0, chances: 0 }
+    }
+
+    /// Books `count` more dice with `sides` sides each.
+    pub(crate) fn dice(&mut self, count: u32, sides: u32) -> Result<(), RollError> {
+        self.dice += u64::from(count);
+        if count > self.limits.dice {
+            Err(RollError::LimitExceeded(Limit::Dice(self.limits.dice)))
+        } else if self.dice > self.limits.total_dice {
+            Err(RollError::LimitExceeded(Limit::TotalDice(self.limits.total_dice)))
+        } else if sides > self.limits.sides {
+            Err(RollError::LimitExceeded(Limit::Sides(self.limits.sides)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Books one more evaluation step.
+    pub(crate) fn step(&mut self) -> Result<(), RollError> {
+        self.steps += 1;
+        if self.steps > self.limits.steps {
+            Err(RollError::LimitExceeded(Limit::Steps(self.limits.steps)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Books `n` more chances computed for a distribution.
+    pub(crate) fn chances(&mut self, n: u64) -> Result<(), RollError> {
+        self.chances = self.chances.saturating_add(n);
+        if self.chances > self.limits.chances {
+            Err(RollError::LimitExceeded(Limit::Chances(self.limits.chances)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod limits_test {
+    use super::*;
+    use error::ParseError;
+    use {Explode, ExplodeMode, Expr, RollCommand};
+
+    fn parse_error(s: &str, limits: &Limits) -> ParseError {
+        Expr::parse_with(s, limits).unwrap_err()
+    }
+
+    #[test]
+    fn parser_rejects_large_terms() {
+        let limits = Limits::default();
+        let err = parse_error("4000000000d6", &limits);
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::Dice(1000), span: 0..10 }, err);
+        let err = parse_error("d2000000", &limits);
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::Sides(1_000_000), span: 1..8 }, err);
+        let err = parse_error("2000000", &limits);
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::Sides(1_000_000), span: 0..7 }, err);
+
+        let limits = Limits::default().total_dice(100);
+        let err = parse_error("60d6 + 60d6", &limits);
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::TotalDice(100), span: 7..9 }, err);
+    }
+
+    #[test]
+    fn parser_rejects_large_expressions() {
+        let limits = Limits::default().nodes(5);
+        assert!(Expr::parse_with("1 + 2 + d6", &limits).is_ok());
+        let err = parse_error("1 + 2 + 3 + d6", &limits);
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::Nodes(5), span: 10..11 }, err);
+
+        // nesting far deeper than the stack would allow
+        let deep = format!("{}d6{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(deep.parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn evaluator_enforces_limits() {
+        let err = RollCommand::new(4_000_000_000, 6).result(&mut |max| max).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Dice(1000))), err);
+
+        let explode = Explode::new(ExplodeMode::Explode).depth(1000);
+        let err = RollCommand::new(1, 6).explode(explode).result(&mut |max| max).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::ExplosionDepth(100))), err);
+
+        let limits = Limits::default().steps(10);
+        let err = RollCommand::new(20, 6).result_with(&mut |max| max, &limits).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Steps(10))), err);
+    }
+
+    #[test]
+    fn evaluator_rejects_large_expressions() {
+        let expr: Expr = "1 + 2 + 3 + d6".parse().unwrap();
+        let limits = Limits::default().nodes(5);
+        let err = expr.result_with(&mut |max| max, &limits).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Nodes(5))), err);
+    }
+}
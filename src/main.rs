@@ -1,32 +1,406 @@
-extern crate rand;
 extern crate rcmd;
+extern crate rustyline;
+extern crate serde;
+extern crate serde_json;
 
-use rand::{ OsRng, Rng };
-use rcmd::RollCommand;
-use std::error::Error;
+mod batch;
+mod cli;
+mod format;
+mod repl;
 
-fn main() {
-    // attempt to retrieve randomness from the os
-    let mut rng = match OsRng::new() {
-        Ok(rng) => rng, 
+use std::process;
+
+use batch::Line;
+use cli::{Export, EXIT_FAILURE, EXIT_IO, EXIT_RNG, EXIT_USAGE};
+use format::{csv_string, json_string, Format, Printer, Record};
+use rcmd::{fair, Commitment, DieSource, Expr, FairError, FairRoller, OsSource, Order, Query};
+use rcmd::{Repeat, RepeatResult, RollError, SeededSource, ServerSeed, Table};
+
+const VERIFY_USAGE: &str =
+    "usage: dice verify <commitment> <server seed> <client seed> <nonce> <expression> [result]";
+
+/// Keeps the exit code of the first failure, stopping at it unless
+/// `--keep-going` was given.
+struct Status {
+    keep_going: bool,
+    code: Option<i32>,
+}
+
+impl Status {
+    fn new(keep_going: bool) -> Status {
+        Status { keep_going, code: None }
+    }
+
+    /// Records a failure, exiting with `code` right away unless going on.
+    fn fail(&mut self, code: i32) {
+        if !self.keep_going {
+            process::exit(code);
+        }
+        self.code = self.code.or(Some(code));
+    }
+
+    /// Exits with the code of the first failure, if there was one.
+    fn exit(self) {
+        if let Some(code) = self.code {
+            process::exit(code);
+        }
+    }
+}
+
+/// Parses every expression, reporting each one that fails, and sorts the
+/// rolls of repeats in `order` if given. Failures stop before anything is
+/// rolled, unless going on.
+fn parse_exprs<'a>(
+    lines: &'a [Line],
+    order: Option<Order>,
+    status: &mut Status,
+) -> Vec<(&'a Line, Repeat)> {
+    let mut exprs = Vec::new();
+    let mut failed = None;
+    for line in lines {
+        match line.text.parse::<Repeat>() {
+            Ok(repeat) => match order {
+                Some(order) => exprs.push((line, repeat.sort(order))),
+                None => exprs.push((line, repeat)),
+            },
+            Err(e) => {
+                eprintln!("{}", line.parse_error(&e));
+                failed = failed.or(Some(cli::parse_code(&e)));
+            }
+        }
+    }
+    if let Some(code) = failed {
+        status.fail(code);
+    }
+    exprs
+}
+
+/// Generates a server seed and prints it along with its commitment.
+fn commit() {
+    match ServerSeed::generate() {
+        Ok(seed) => {
+            println!("commitment: {}", seed.commitment());
+            println!("server seed: {}", seed);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_RNG);
+        }
+    }
+}
+
+/// Rolls a provably fair roll again from the revealed server seed, and
+/// compares it with the result claimed, if any.
+fn verify(args: &[String]) {
+    if args.len() < 5 || args.len() > 6 {
+        eprintln!("{}", VERIFY_USAGE);
+        process::exit(EXIT_USAGE);
+    }
+    let commitment = args[0].parse::<Commitment>();
+    let seed = args[1].parse::<ServerSeed>();
+    let (commitment, seed) = match (commitment, seed) {
+        (Ok(commitment), Ok(seed)) => (commitment, seed),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let nonce = match args[3].parse::<u64>() {
+        Ok(nonce) => nonce,
+        Err(_) => {
+            eprintln!("the nonce is a number between 0 and {}", u64::MAX);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let repeat = match args[4].parse::<Repeat>() {
+        Ok(repeat) => repeat,
+        Err(e) => {
+            eprintln!("{}", e.render(&args[4]));
+            process::exit(cli::parse_code(&e));
+        }
+    };
+
+    let rolled = fair::verified_source(&commitment, &seed, &args[2], nonce)
+        .and_then(|mut source| repeat.result(&mut source).map_err(FairError::Roll));
+    match rolled {
+        Ok(result) => match args.get(5) {
+            Some(claimed) if claimed.trim() != result.to_string() => {
+                println!("mismatch: claimed {}, rolled {}", claimed.trim(), result);
+                process::exit(EXIT_FAILURE);
+            }
+            _ => println!("verified: {}", result),
+        },
         Err(e) => {
-            println!("{}", e.description());
-            return;
-        }, 
+            eprintln!("{}", e);
+            process::exit(match e {
+                FairError::Roll(e) => cli::roll_code(&e),
+                _ => EXIT_FAILURE,
+            });
+        }
+    }
+}
+
+/// Prints the chance of every query, along with the expected damage of an
+/// attack hitting when it holds if `--damage` is given. Every query is
+/// answered, even after one fails.
+fn prob(args: &[String]) {
+    let mut damage = None;
+    let mut queries = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--damage" {
+            match args.next().map(|s| (s, s.parse::<Expr>())) {
+                Some((_, Ok(expr))) => damage = Some(expr),
+                Some((s, Err(e))) => {
+                    eprintln!("{}", e.render(s));
+                    process::exit(cli::parse_code(&e));
+                }
+                None => {
+                    eprintln!("--damage needs the damage dealt by a hit");
+                    process::exit(EXIT_USAGE);
+                }
+            }
+        } else {
+            queries.push(arg);
+        }
+    }
+
+    let mut status = Status::new(true);
+    for arg in queries {
+        let query = match arg.parse::<Query>() {
+            Ok(query) => query,
+            Err(e) => {
+                eprintln!("{}", e.render(arg));
+                if let Some(hint) = cli::query_hint(arg) {
+                    eprintln!("{}", hint);
+                }
+                status.fail(cli::parse_code(&e));
+                continue;
+            }
+        };
+        let chance = query.chance();
+        let expected = damage.as_ref().map(|damage| query.expected_damage(damage));
+        match (chance, expected) {
+            (Ok(chance), None) => println!("{}: {:.2}%", query, chance * 100.0),
+            (Ok(chance), Some(Ok(expected))) => {
+                println!("{}: {:.2}%, expected damage {:.2}", query, chance * 100.0, expected)
+            }
+            (Err(e), _) | (_, Some(Err(e))) => {
+                eprintln!("{}: {}", arg, e);
+                status.fail(cli::roll_code(&e));
+            }
+        }
+    }
+    status.exit();
+}
+
+/// Rolls every expression with `roll`, which returns the nonce of a
+/// provably fair roll along with the results, and prints the results,
+/// followed by their aggregate for repeats if `aggregate` is set.
+fn roll_all<F>(
+    exprs: Vec<(&Line, Repeat)>,
+    seed: Option<u64>,
+    format: Format,
+    aggregate: bool,
+    status: &mut Status,
+    mut roll: F,
+) where
+    F: FnMut(&Repeat) -> Result<(Option<u64>, RepeatResult), RollError>,
+{
+    let mut printer = Printer::new(format);
+    for (line, repeat) in exprs {
+        match roll(&repeat) {
+            Ok((nonce, results)) => {
+                let expression = repeat.expr().to_string();
+                for result in results.iter() {
+                    printer.print(&Record { expression: expression.clone(), result, seed, nonce });
+                }
+                if aggregate && repeat.count() > 1 {
+                    println!("{}", results.aggregate());
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", line.roll_error(&e));
+                // what was printed stays valid when stopping here
+                if !status.keep_going {
+                    printer.finish();
+                }
+                status.fail(cli::roll_code(&e));
+            }
+        }
+    }
+    printer.finish();
+}
+
+/// Prints the distribution of every expression as a bar chart, or exports
+/// it as CSV or JSON.
+///
+/// The distribution of a repeat is the one of each of its rolls.
+fn dist(exprs: &[(&Line, Repeat)], table: Table, export: Export, status: &mut Status) {
+    let column = match table {
+        Table::Exactly => "chance",
+        Table::AtLeast => "at_least",
+        Table::AtMost => "at_most",
     };
+    let mut charts = 0;
+    let mut entries = Vec::new();
+    if let Export::Csv = export {
+        println!("expression,value,{}", column);
+    }
+    for &(line, ref repeat) in exprs {
+        let expr = repeat.expr();
+        let dist = match expr.distribution() {
+            Ok(dist) => dist,
+            Err(e) => {
+                eprintln!("{}", line.roll_error(&e));
+                status.fail(cli::roll_code(&e));
+                continue;
+            }
+        };
+        match export {
+            Export::Chart => {
+                if charts > 0 {
+                    println!();
+                }
+                println!("{}: mean {:.2}, stddev {:.2}", expr, dist.mean(), dist.stddev());
+                print!("{}", dist.histogram(table, 50));
+                charts += 1;
+            }
+            Export::Csv => {
+                let quoted = csv_string(&expr.to_string());
+                for (value, chance) in dist.table(table) {
+                    println!("{},{},{}", quoted, value, chance);
+                }
+            }
+            Export::Json => {
+                let rows: Vec<_> = dist
+                    .table(table)
+                    .iter()
+                    .map(|&(value, chance)| format!("[{},{}]", value, chance))
+                    .collect();
+                entries.push(format!(
+                    "{{\"expression\":{},\"mean\":{},\"stddev\":{},\"{}\":[{}]}}",
+                    json_string(&expr.to_string()),
+                    dist.mean(),
+                    dist.stddev(),
+                    column,
+                    rows.join(",")
+                ));
+            }
+        }
+    }
+    if let Export::Json = export {
+        println!("[{}]", entries.join(","));
+    }
+}
+
+fn main() {
+    // `dice commit` and `dice verify ...` run the provably fair subcommands,
+    // `dice prob ...` answers probability queries
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("commit") => return commit(),
+        Some("verify") => return verify(&args[1..]),
+        Some("prob") => return prob(&args[1..]),
+        _ => {}
+    }
 
     // 1. get command von args
-    // 2. filtermap args as rollcommands, discarding failures.
-    // 3. Map commands to results.
-    // 4. Collect results into a vector.
-    let rolls: Vec<_> = std::env::args()
-        .filter_map(|arg| arg.parse::<RollCommand>().ok())
-        .map(|cmd| cmd.result(|max| rng.gen_range(0, max) + 1))
-        .collect();
+    //    a seed given with --seed replaces the os randomness, a server and
+    //    client seed roll provably fair results, and --dist prints the
+    //    distributions instead of rolling
+    let options = match cli::parse(args) {
+        Ok(options) => options,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("try `dice --help` for usage");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    if options.version {
+        println!("dice {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    // expressions given as arguments, and from - or --file, in order
+    let interactive = options.interactive;
+    let lines = match batch::read(options.inputs) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_IO);
+        }
+    };
 
-    // Print results
-    for roll in rolls {
-        println!("{}", roll);
+    // without any expressions, or with -i, roll the expression on every
+    // line typed, after the ones given; cli::parse has already checked the
+    // options apply
+    if interactive {
+        let mut session = match repl::Session::new(options.seed) {
+            Ok(session) => session.sort(options.order).aggregate(options.aggregate),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_RNG);
+            }
+        };
+        for line in &lines {
+            match session.eval(&line.text) {
+                repl::Reply::Print(s) => println!("{}", s),
+                repl::Reply::Error(s) => eprintln!("{}", s),
+                _ => {}
+            }
+        }
+        if let Err(e) = repl::run(session) {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+        return;
     }
 
-}
\ No newline at end of file
+    // 2. parse args as roll expressions, reporting failures.
+    let mut status = Status::new(options.keep_going);
+    let exprs = parse_exprs(&lines, options.order, &mut status);
+    if let Some(table) = options.table {
+        dist(&exprs, table, options.export, &mut status);
+        return status.exit();
+    }
+
+    // 3. Map commands to results.
+    //    provably fair rolls print the nonce of every roll for verification
+    if let Some((server_seed, client_seed)) = options.fair {
+        let mut roller = FairRoller::new(server_seed, &client_seed).nonce(options.nonce);
+        // structured output only holds records, the commitment is for people
+        match options.format {
+            Format::Plain => println!("commitment: {}", roller.commitment()),
+            _ => eprintln!("commitment: {}", roller.commitment()),
+        }
+        // every repeat is rolled with a single nonce
+        roll_all(exprs, None, options.format, options.aggregate, &mut status, |repeat| {
+            let (nonce, mut source) = roller.next_source();
+            repeat.result(&mut source).map(|results| (Some(nonce), results))
+        });
+        return status.exit();
+    }
+
+    // attempt to retrieve randomness from the os, unless seeded
+    let mut source: Box<dyn DieSource> = match options.seed {
+        Some(seed) => Box::new(SeededSource::new(seed)),
+        None => match OsSource::new() {
+            Ok(source) => Box::new(source),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_RNG);
+            }
+        },
+    };
+    roll_all(exprs, options.seed, options.format, options.aggregate, &mut status, |repeat| {
+        repeat.result(&mut *source).map(|results| (None, results))
+    });
+    status.exit();
+}
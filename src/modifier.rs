@@ -0,0 +1,290 @@
+//! Modifiers that change how the dice of a `RollCommand` are rolled and
+//! which of them count towards the total.
+
+use std::fmt;
+
+use error::RollError;
+use limits::Limit;
+use {Die, Faces};
+
+/// Default cap on the number of times a single die may explode.
+///
+/// Without a cap a source that always returns the highest face, such as
+/// `|max| max`, would make an exploding die roll forever.
+pub const DEFAULT_EXPLOSION_DEPTH: u32 = 100;
+
+/// Default cap on the number of times a single die may be rerolled.
+///
+/// Rerolling until a face no die can avoid, such as `d6r<7`, would never
+/// stop otherwise.
+pub const DEFAULT_REROLL_DEPTH: u32 = 100;
+
+/// A comparison against a die face, as used by `d6!>=5` or `d6r<3`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Compare {
+    Eq(i64),
+    Lt(i64),
+    Le(i64),
+    Gt(i64),
+    Ge(i64),
+}
+
+impl Compare {
+    /// Returns true if `face` satisfies the comparison.
+    ///
+    /// Takes the value of a whole die as well as a single face, so it
+    /// accepts anything that widens to `i128`.
+    pub fn matches<T: Into<i128>>(self, face: T) -> bool {
+        let face = face.into();
+        match self {
+            Compare::Eq(n) => face == i128::from(n),
+            Compare::Lt(n) => face < i128::from(n),
+            Compare::Le(n) => face <= i128::from(n),
+            Compare::Gt(n) => face > i128::from(n),
+            Compare::Ge(n) => face >= i128::from(n),
+        }
+    }
+}
+
+impl fmt::Display for Compare {
+    /// Writes the comparison the way it is written after a die: ">=5"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compare::Eq(n) => write!(f, "={}", n),
+            Compare::Lt(n) => write!(f, "<{}", n),
+            Compare::Le(n) => write!(f, "<={}", n),
+            Compare::Gt(n) => write!(f, ">{}", n),
+            Compare::Ge(n) => write!(f, ">={}", n),
+        }
+    }
+}
+
+/// Selects the dice of a roll that count towards its total.
+///
+/// 4d6kh3 => Highest(3), 2d20kl1 => Lowest(1), 5d10dh2 => DropHighest(2),
+/// 4d6dl1 => DropLowest(1)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Keep {
+    Highest(u32),
+    Lowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+}
+
+impl Keep {
+    /// Returns whether the highest or the lowest dice are kept, and how
+    /// many of `count` dice that is.
+    pub(crate) fn kept(self, count: u32) -> (bool, u32) {
+        match self {
+            Keep::Highest(n) => (true, n.min(count)),
+            Keep::Lowest(n) => (false, n.min(count)),
+            Keep::DropHighest(n) => (false, count.saturating_sub(n)),
+            Keep::DropLowest(n) => (true, count.saturating_sub(n)),
+        }
+    }
+
+    /// Marks the dice that are not kept as dropped.
+    ///
+    /// Exploded dice are kept or dropped as a whole. Among equal values the
+    /// die rolled first is kept, so results stay
+    /// stable for a given sequence of faces.
+    pub(crate) fn apply(self, dice: &mut [Die]) {
+        let (highest, n) = self.kept(dice.len() as u32);
+
+        let mut order: Vec<usize> = (0..dice.len()).collect();
+        if highest {
+            order.sort_by(|&a, &b| dice[b].value().cmp(&dice[a].value()));
+        } else {
+            order.sort_by(|&a, &b| dice[a].value().cmp(&dice[b].value()));
+        }
+        for &i in order.iter().skip(n as usize) {
+            dice[i].kept = false;
+        }
+    }
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Keep::Highest(n) => write!(f, "kh{}", n),
+            Keep::Lowest(n) => write!(f, "kl{}", n),
+            Keep::DropHighest(n) => write!(f, "dh{}", n),
+            Keep::DropLowest(n) => write!(f, "dl{}", n),
+        }
+    }
+}
+
+/// How the extra rolls of an exploding die are added up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum ExplodeMode {
+    /// `d6!`: every explosion adds another roll of the die.
+    Explode,
+    /// `d6!!`: like `Explode`, but the rolls compound into a single die, so
+    /// they are counted as one when counting successes.
+    Compound,
+    /// `d6!p`: like `Explode`, but every extra roll is one lower.
+    Penetrate,
+}
+
+/// Rerolls a die and adds the new face whenever it hits a trigger.
+///
+/// The trigger defaults to the highest face of the die; `d6!>=5` explodes
+/// on 5 and 6 instead. A die explodes at most `depth` times.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Explode {
+    mode: ExplodeMode,
+    on: Option<Compare>,
+    pub(crate) depth: u32,
+}
+
+impl Explode {
+    /// Constructs an explosion triggered by the highest face of the die.
+    pub fn new(mode: ExplodeMode) -> Explode {
+        Explode { mode, on: None, depth: DEFAULT_EXPLOSION_DEPTH }
+    }
+
+    /// Returns this explosion triggered by `on` instead of the highest face.
+    pub fn on(self, on: Compare) -> Explode {
+        Explode { on: Some(on), ..self }
+    }
+
+    /// Returns this explosion capped at `depth` extra rolls per die.
+    pub fn depth(self, depth: u32) -> Explode {
+        Explode { depth, ..self }
+    }
+
+    pub fn mode(&self) -> ExplodeMode {
+        self.mode
+    }
+
+    /// Returns this explosion without a trigger if it is only triggered by
+    /// the highest of `faces`, which is the same as having none.
+    pub(crate) fn simplify(self, faces: &Faces) -> Explode {
+        match self.on {
+            Some(Compare::Eq(n)) if n == faces.highest() => Explode { on: None, ..self },
+            _ => self,
+        }
+    }
+
+    /// Returns this explosion with its trigger written out, which is the
+    /// highest of `faces` if it has none.
+    pub(crate) fn explicit(self, faces: &Faces) -> Explode {
+        Explode { on: Some(self.on.unwrap_or(Compare::Eq(faces.highest()))), ..self }
+    }
+
+    pub(crate) fn triggers(&self, face: i64, faces: &Faces) -> bool {
+        match self.on {
+            Some(on) => on.matches(face),
+            None => face == faces.highest(),
+        }
+    }
+
+    /// Rolls the explosion chain of a die that came up `face`, failing if
+    /// the chain grows longer than `limit`.
+    pub(crate) fn chain<P>(
+        &self,
+        face: i64,
+        faces: &Faces,
+        pick: &mut P,
+        limit: u32,
+    ) -> Result<Vec<i64>, RollError>
+    where
+        P: FnMut(u32) -> Result<u32, RollError>,
+    {
+        let mut chain = Vec::new();
+        let mut last = face;
+        while chain.len() < self.depth as usize && self.triggers(last, faces) {
+            if chain.len() == limit as usize {
+                return Err(RollError::LimitExceeded(Limit::ExplosionDepth(limit)));
+            }
+            last = faces.roll(pick)?;
+            chain.push(match self.mode {
+                ExplodeMode::Penetrate => last - 1,
+                _ => last,
+            });
+        }
+        Ok(chain)
+    }
+}
+
+impl fmt::Display for Explode {
+    /// Writes the explosion in dice notation: "!", "!!>=5", "!p"
+    ///
+    /// The depth is not part of the notation and is left out.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.mode {
+            ExplodeMode::Explode => "!",
+            ExplodeMode::Compound => "!!",
+            ExplodeMode::Penetrate => "!p",
+        })?;
+        match self.on {
+            Some(on) => write!(f, "{}", on),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rolls a die again when its face hits a trigger, discarding the old face.
+///
+/// `d6r1` rerolls until the face is no longer a 1, `d6ro<3` rerolls a face
+/// below 3 only once and keeps whatever comes up next. A die is rerolled
+/// at most `depth` times.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Reroll {
+    pub(crate) on: Compare,
+    once: bool,
+    pub(crate) depth: u32,
+}
+
+impl Reroll {
+    /// Constructs a reroll that repeats until the face no longer matches.
+    pub fn until(on: Compare) -> Reroll {
+        Reroll { on, once: false, depth: DEFAULT_REROLL_DEPTH }
+    }
+
+    /// Constructs a reroll that happens at most once per die.
+    pub fn once(on: Compare) -> Reroll {
+        Reroll { on, once: true, depth: 1 }
+    }
+
+    /// Returns this reroll capped at `depth` rerolls per die.
+    pub fn depth(self, depth: u32) -> Reroll {
+        Reroll { depth, ..self }
+    }
+
+    /// Rerolls `face` in place, returning the faces that were discarded.
+    pub(crate) fn apply<P>(
+        &self,
+        face: &mut i64,
+        faces: &Faces,
+        pick: &mut P,
+    ) -> Result<Vec<i64>, RollError>
+    where
+        P: FnMut(u32) -> Result<u32, RollError>,
+    {
+        let mut discarded = Vec::new();
+        while discarded.len() < self.depth as usize && self.on.matches(*face) {
+            discarded.push(*face);
+            *face = faces.roll(pick)?;
+        }
+        Ok(discarded)
+    }
+}
+
+impl fmt::Display for Reroll {
+    /// Writes the reroll in dice notation: "r1", "ro<3"
+    ///
+    /// The depth is not part of the notation and is left out.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.once { "ro" } else { "r" })?;
+        match self.on {
+            Compare::Eq(n) if n >= 0 => write!(f, "{}", n),
+            on => write!(f, "{}", on),
+        }
+    }
+}
@@ -0,0 +1,809 @@
+//! Recursive descent parser for roll commands and roll expressions.
+//!
+//! The input is split into tokens by `lex::tokenize` first. The grammar
+//! understood by the parser:
+//!
+//! ```text
+//! expr   := term (('+' | '-') term)*
+//! term   := factor ('*' factor)*
+//! factor := pool | dice | number | '(' expr ')'
+//! pool   := dice compare ('f' (compare | number))?
+//! dice   := number? 'd' (number | 'F' | '%' | faces) modifier*
+//! faces  := '{' face (',' face)* '}'
+//! face   := '-'? number | symbol
+//! modifier := reroll | explode | keep
+//! reroll   := ('r' | 'ro') (compare | number)
+//! explode  := ('!' | '!!' | '!p') (compare | number)?
+//! keep     := ('kh' | 'kl' | 'k' | 'dh' | 'dl') number
+//! compare  := ('=' | '<' | '<=' | '>' | '>=') '-'? number
+//! query    := expr ('=' | '<' | '<=' | '>' | '>=') expr
+//! repeat   := number 'x' expr | 'repeat' '(' number ',' expr ')' | expr
+//! ```
+//!
+//! Each kind of modifier may appear at most once per dice term. An explosion
+//! triggered by the highest face only is the same as one without a trigger.
+//!
+//! Whitespace is allowed between tokens, but not inside a dice term. Every
+//! entry point either consumes its whole input or fails.
+//!
+//! Input going over the given `Limits` is rejected while parsing, before
+//! anything gets allocated for it.
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use error::ParseError;
+use expr::{Expr, Op};
+use lex::{self, Token, TokenKind};
+use limits::{Limit, Limits};
+use query::{Query, Relation};
+use repeat::Repeat;
+use {Compare, Explode, ExplodeMode, Faces, Keep, PoolCommand, Reroll, RollCommand};
+
+/// Parses a single dice term such as `2d6`, `d6` or the shorthand `6`.
+pub fn roll_command(s: &str, limits: &Limits) -> Result<RollCommand, ParseError> {
+    let mut parser = Parser::new(s, limits)?;
+    let cmd = match parser.count()? {
+        None => parser.dice(1)?,
+        Some(count) if parser.attached_word("d") => parser.dice(count)?,
+        Some(0) => return Err(ParseError::ZeroSides { span: parser.last_span() }),
+        Some(range) => {
+            parser.check_sides(range, parser.last_span())?;
+            RollCommand::new(1, range)
+        }
+    };
+    parser.finish()?;
+    Ok(cmd)
+}
+
+/// Parses a success-counting pool such as `8d10>=7` or `10d10>=8f1`.
+pub fn pool_command(s: &str, limits: &Limits) -> Result<PoolCommand, ParseError> {
+    let mut parser = Parser::new(s, limits)?;
+    let count = parser.count()?;
+    let roll = parser.dice(count.unwrap_or(1))?;
+    let pool = match parser.pool(roll)? {
+        Expr::Pool(pool) => pool,
+        _ => return Err(parser.unexpected()),
+    };
+    parser.finish()?;
+    Ok(pool)
+}
+
+/// Parses a full arithmetic roll expression.
+///
+/// A lone number is shorthand for a single die, just like it is for
+/// `RollCommand`, so `"6"` parses as `1d6` rather than the constant 6.
+pub fn expression(s: &str, limits: &Limits) -> Result<Expr, ParseError> {
+    let mut parser = Parser::new(s, limits)?;
+    let expr = parser.expr()?;
+    parser.finish()?;
+    parser.shorthand(expr, 0)
+}
+
+/// Parses a comparison between two expressions, such as `1d20 + 5 >= 15`.
+///
+/// Unlike in `expression`, a lone number is a constant.
+pub fn query(s: &str, limits: &Limits) -> Result<Query, ParseError> {
+    let mut parser = Parser::new(s, limits)?;
+    let lhs = parser.expr()?;
+    let relation = if parser.eat("<=") {
+        Relation::Le
+    } else if parser.eat(">=") {
+        Relation::Ge
+    } else if parser.eat("<") {
+        Relation::Lt
+    } else if parser.eat(">") {
+        Relation::Gt
+    } else if parser.eat("=") {
+        Relation::Eq
+    } else {
+        return Err(parser.unexpected());
+    };
+    let rhs = parser.expr()?;
+    parser.finish()?;
+    Ok(Query::new(lhs, relation, rhs))
+}
+
+/// Parses an expression rolled several times, such as `6x4d6kh3` or
+/// `repeat(6, 4d6kh3)`, or a plain expression, which is rolled once.
+///
+/// The repeated expression must roll dice. A lone number is shorthand for a
+/// single die only when it is not repeated, just like in `expression`.
+pub fn repeat(s: &str, limits: &Limits) -> Result<Repeat, ParseError> {
+    let mut parser = Parser::new(s, limits)?;
+    let (count, span) = match parser.tokens.get(..2) {
+        Some([Token { kind: TokenKind::Word("repeat"), .. }, _]) => {
+            parser.next += 1;
+            if !parser.eat("(") {
+                return Err(parser.unexpected());
+            }
+            let count = parser.repeat_count()?;
+            if !parser.eat(",") {
+                return Err(parser.unexpected());
+            }
+            count
+        }
+        Some([Token { kind: TokenKind::Number(_), .. }, x])
+            if x.kind == TokenKind::Word("x") && !x.spaced =>
+        {
+            let count = parser.repeat_count()?;
+            parser.next += 1;
+            count
+        }
+        _ => {
+            let expr = parser.expr()?;
+            parser.finish()?;
+            return Ok(Repeat::new(1, parser.shorthand(expr, 0)?));
+        }
+    };
+
+    // unlike a whole expression, a lone number here is not a die, so that
+    // `2x6` is not taken for `2x1d6`
+    let start = parser.expected_span().start;
+    let expr = parser.expr()?;
+    if parser.dice == 0 {
+        return Err(ParseError::MissingDice { span: start..parser.last_span().end });
+    }
+    if parser.tokens[0].kind == TokenKind::Word("repeat") && !parser.eat(")") {
+        return Err(parser.unexpected());
+    }
+    parser.finish()?;
+
+    // every repetition rolls the dice of the expression again
+    if parser.dice.saturating_mul(u64::from(count)) > limits.total_dice {
+        let limit = Limit::TotalDice(limits.total_dice);
+        return Err(ParseError::LimitExceeded { limit, span });
+    }
+    Ok(Repeat::new(count, expr))
+}
+
+/// Cursor over the tokens of a roll expression.
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token<'a>>,
+    next: usize,
+    limits: &'a Limits,
+    /// Terms, operators and parentheses parsed so far.
+    nodes: usize,
+    /// Dice in all the terms parsed so far.
+    dice: u64,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, limits: &'a Limits) -> Result<Parser<'a>, ParseError> {
+        let tokens = lex::tokenize(src)?;
+        Ok(Parser { src, tokens, next: 0, limits, nodes: 0, dice: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next)
+    }
+
+    /// Returns the next token, unless whitespace separates it from the
+    /// previous one.
+    fn peek_attached(&self) -> Option<&Token<'a>> {
+        self.peek().filter(|t| !t.spaced)
+    }
+
+    /// Consumes the next token if it is the punctuation `p`.
+    fn eat(&mut self, p: &str) -> bool {
+        match self.peek() {
+            Some(&Token { kind: TokenKind::Punct(punct), .. }) if punct == p => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Consumes the next token if it is the punctuation `p` and directly
+    /// follows the previous token.
+    fn eat_attached(&mut self, p: &str) -> bool {
+        self.peek_attached().is_some() && self.eat(p)
+    }
+
+    /// Returns true if the next token is the keyword `w`, directly following
+    /// the previous token.
+    fn attached_word(&self, w: &str) -> bool {
+        match self.peek_attached() {
+            Some(&Token { kind: TokenKind::Word(word), .. }) => word == w,
+            _ => false,
+        }
+    }
+
+    /// Consumes the next token if it is the keyword `w` and directly follows
+    /// the previous token.
+    fn eat_word(&mut self, w: &str) -> bool {
+        if self.attached_word(w) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if the next token is a number directly following the
+    /// previous token.
+    fn number_follows(&self) -> bool {
+        matches!(self.peek_attached(), Some(&Token { kind: TokenKind::Number(_), .. }))
+    }
+
+    /// Returns the span of the last token consumed.
+    fn last_span(&self) -> Range<usize> {
+        match self.next {
+            0 => 0..0,
+            n => self.tokens[n - 1].span.clone(),
+        }
+    }
+
+    /// Returns the span where something directly following the previous
+    /// token was expected: the next token if it is attached, or the empty
+    /// span right after the previous token otherwise.
+    fn expected_span(&self) -> Range<usize> {
+        match self.peek_attached() {
+            Some(token) => token.span.clone(),
+            None => {
+                let end = self.last_span().end;
+                end..end
+            }
+        }
+    }
+
+    /// Builds an error describing whatever sits at the current position.
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => {
+                let found = self.src[token.span.clone()].chars().next().unwrap_or(' ');
+                ParseError::UnexpectedChar { found, span: token.span.clone() }
+            }
+            None => {
+                let end = self.src.len();
+                ParseError::UnexpectedEnd { span: end..end }
+            }
+        }
+    }
+
+    /// Fails unless the whole input has been consumed.
+    fn finish(&self) -> Result<(), ParseError> {
+        match (self.peek(), self.tokens.last()) {
+            (Some(next), Some(last)) => {
+                Err(ParseError::TrailingInput { span: next.span.start..last.span.end })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Books one more node of the expression tree, located at `span`.
+    fn node(&mut self, span: Range<usize>) -> Result<(), ParseError> {
+        self.nodes += 1;
+        if self.nodes > self.limits.nodes {
+            return Err(ParseError::LimitExceeded { limit: Limit::Nodes(self.limits.nodes), span });
+        }
+        Ok(())
+    }
+
+    /// Books `count` more dice, the count of which is located at `span`.
+    fn check_count(&mut self, count: u32, span: Range<usize>) -> Result<(), ParseError> {
+        self.dice += u64::from(count);
+        let limit = if count > self.limits.dice {
+            Limit::Dice(self.limits.dice)
+        } else if self.dice > self.limits.total_dice {
+            Limit::TotalDice(self.limits.total_dice)
+        } else {
+            return Ok(());
+        };
+        Err(ParseError::LimitExceeded { limit, span })
+    }
+
+    /// Fails if dice with `sides` sides, located at `span`, are too large.
+    fn check_sides(&self, sides: u32, span: Range<usize>) -> Result<(), ParseError> {
+        if sides > self.limits.sides {
+            let limit = Limit::Sides(self.limits.sides);
+            return Err(ParseError::LimitExceeded { limit, span });
+        }
+        Ok(())
+    }
+
+    /// Converts the digits of a number token into an integer of type `T`.
+    fn convert<T: FromStr>(&self, digits: &str, span: Range<usize>) -> Result<T, ParseError> {
+        digits.parse().map_err(|_| ParseError::Overflow { span })
+    }
+
+    /// Parses a number directly following the previous token.
+    fn number(&mut self) -> Result<u32, ParseError> {
+        match self.peek_attached().cloned() {
+            Some(Token { kind: TokenKind::Number(digits), span, .. }) => {
+                self.next += 1;
+                self.convert(digits, span)
+            }
+            _ => Err(ParseError::MissingCount { span: self.expected_span() }),
+        }
+    }
+
+    /// Parses the optional count at the start of a dice term.
+    fn count(&mut self) -> Result<Option<u32>, ParseError> {
+        match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Number(digits), span, .. }) => {
+                self.next += 1;
+                Ok(Some(self.convert(digits, span)?))
+            }
+            Some(Token { kind: TokenKind::Word("d"), .. }) => Ok(None),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses the `'d'` and everything after it in a dice term, given its count.
+    fn dice(&mut self, count: u32) -> Result<RollCommand, ParseError> {
+        // a term may start after whitespace, but its 'd' must follow the count
+        let d = self.peek().is_some_and(|t| t.kind == TokenKind::Word("d"));
+        if !d || (self.count_was_read() && !self.attached_word("d")) {
+            return Err(self.unexpected());
+        }
+        let count_span = if self.count_was_read() {
+            self.last_span()
+        } else {
+            self.expected_span()
+        };
+        if count == 0 {
+            return Err(ParseError::ZeroCount { span: count_span });
+        }
+        self.check_count(count, count_span)?;
+        self.next += 1;
+
+        let start = self.expected_span().start;
+        let faces = if self.eat_word("F") {
+            Faces::Fate
+        } else if self.eat_attached("%") {
+            Faces::Numbered(100)
+        } else if self.eat_attached("{") {
+            self.faces()?
+        } else {
+            match self.number()? {
+                0 => return Err(ParseError::ZeroSides { span: self.last_span() }),
+                range => Faces::Numbered(range),
+            }
+        };
+        self.check_sides(faces.sides(), start..self.last_span().end)?;
+        self.modifiers(RollCommand::with_faces(count, faces))
+    }
+
+    /// Returns true if the token before the next one is a dice count.
+    fn count_was_read(&self) -> bool {
+        self.next > 0 && matches!(self.tokens[self.next - 1].kind, TokenKind::Number(_))
+    }
+
+    /// Turns a dice term into a pool if a success comparison follows it.
+    fn pool(&mut self, roll: RollCommand) -> Result<Expr, ParseError> {
+        let success = match self.compare()? {
+            Some(success) => success,
+            None => return Ok(Expr::Roll(roll)),
+        };
+        let pool = PoolCommand::new(roll, success);
+        if !self.eat_word("f") {
+            return Ok(Expr::Pool(pool));
+        }
+        Ok(Expr::Pool(pool.failure(self.target()?)))
+    }
+
+    /// Parses a list of custom faces, after the opening brace.
+    ///
+    /// The faces are numbers if every one of them is a number, and symbols
+    /// if none of them is; mixing both is an error.
+    fn faces(&mut self) -> Result<Faces, ParseError> {
+        let start = self.last_span().start;
+        let mut labels = Vec::new();
+        loop {
+            let negative = self.eat_attached("-");
+            match self.peek_attached().cloned() {
+                Some(Token { kind: TokenKind::Label(label), .. }) => {
+                    self.next += 1;
+                    labels.push((negative, label));
+                }
+                _ => return Err(self.unexpected()),
+            }
+            if self.eat_attached("}") {
+                break;
+            }
+            if !self.eat_attached(",") {
+                return Err(self.unexpected());
+            }
+        }
+
+        let span = start..self.last_span().end;
+        let is_number = |label: &str| label.bytes().all(|b| b.is_ascii_digit());
+        if labels.iter().all(|&(_, label)| is_number(label)) {
+            let mut numbers = Vec::new();
+            for (negative, label) in labels {
+                let n: i64 = self.convert(label, span.clone())?;
+                numbers.push(if negative { -n } else { n });
+            }
+            Ok(Faces::Custom(numbers))
+        } else if labels.iter().all(|&(negative, label)| !negative && !is_number(label)) {
+            Ok(Faces::Symbols(labels.iter().map(|&(_, label)| label.to_string()).collect()))
+        } else {
+            Err(ParseError::MixedFaces { span })
+        }
+    }
+
+    /// Parses the modifiers directly following a die, in any order.
+    fn modifiers(&mut self, mut cmd: RollCommand) -> Result<RollCommand, ParseError> {
+        loop {
+            let start = self.expected_span().start;
+            let duplicate = if let Some(reroll) = self.reroll()? {
+                cmd.reroll.replace(reroll).is_some()
+            } else if let Some(explode) = self.explode()? {
+                cmd.explode.replace(explode.simplify(&cmd.faces)).is_some()
+            } else if let Some(keep) = self.keep()? {
+                cmd.keep.replace(keep).is_some()
+            } else {
+                return Ok(cmd);
+            };
+            if duplicate {
+                return Err(ParseError::DuplicateModifier { span: start..self.last_span().end });
+            }
+        }
+    }
+
+    /// Parses a comparison, or a bare number which must be matched exactly.
+    fn target(&mut self) -> Result<Compare, ParseError> {
+        match self.compare()? {
+            Some(on) => Ok(on),
+            None => Ok(Compare::Eq(i64::from(self.number()?))),
+        }
+    }
+
+    /// Parses an optional reroll modifier. A bare number rerolls that face.
+    fn reroll(&mut self) -> Result<Option<Reroll>, ParseError> {
+        let reroll: fn(Compare) -> Reroll = if self.eat_word("ro") {
+            Reroll::once
+        } else if self.eat_word("r") {
+            Reroll::until
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(reroll(self.target()?)))
+    }
+
+    /// Parses an optional explode modifier.
+    fn explode(&mut self) -> Result<Option<Explode>, ParseError> {
+        let mode = if self.eat_attached("!!") {
+            ExplodeMode::Compound
+        } else if self.eat_attached("!") {
+            if self.eat_word("p") {
+                ExplodeMode::Penetrate
+            } else {
+                ExplodeMode::Explode
+            }
+        } else {
+            return Ok(None);
+        };
+        let explode = Explode::new(mode);
+        Ok(Some(match self.compare()? {
+            Some(on) => explode.on(on),
+            None if self.number_follows() => explode.on(Compare::Eq(i64::from(self.number()?))),
+            None => explode,
+        }))
+    }
+
+    /// Parses an optional comparison such as `>=5`.
+    fn compare(&mut self) -> Result<Option<Compare>, ParseError> {
+        let compare: fn(i64) -> Compare = if self.eat_attached("<=") {
+            Compare::Le
+        } else if self.eat_attached(">=") {
+            Compare::Ge
+        } else if self.eat_attached("<") {
+            Compare::Lt
+        } else if self.eat_attached(">") {
+            Compare::Gt
+        } else if self.eat_attached("=") {
+            Compare::Eq
+        } else {
+            return Ok(None);
+        };
+        let negative = self.eat_attached("-");
+        let n = i64::from(self.number()?);
+        Ok(Some(compare(if negative { -n } else { n })))
+    }
+
+    /// Parses an optional keep or drop modifier directly following a die.
+    fn keep(&mut self) -> Result<Option<Keep>, ParseError> {
+        let keep: fn(u32) -> Keep = if self.eat_word("kh") {
+            Keep::Highest
+        } else if self.eat_word("kl") {
+            Keep::Lowest
+        } else if self.eat_word("k") {
+            Keep::Highest
+        } else if self.eat_word("dh") {
+            Keep::DropHighest
+        } else if self.eat_word("dl") {
+            Keep::DropLowest
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(keep(self.number()?)))
+    }
+
+    /// Turns a lone number into a single die, when it makes up the whole
+    /// expression starting at token `start`.
+    fn shorthand(&self, expr: Expr, start: usize) -> Result<Expr, ParseError> {
+        let span = match self.tokens.get(start) {
+            Some(first) => first.span.start..self.last_span().end,
+            None => 0..0,
+        };
+        match expr {
+            Expr::Constant(0) => Err(ParseError::ZeroSides { span }),
+            Expr::Constant(n) if n <= i64::from(u32::MAX) => {
+                self.check_sides(n as u32, span)?;
+                Ok(Expr::Roll(RollCommand::new(1, n as u32)))
+            }
+            Expr::Constant(_) => Err(ParseError::Overflow { span }),
+            expr => Ok(expr),
+        }
+    }
+
+    /// Parses the number of times an expression is repeated, along with
+    /// its span.
+    fn repeat_count(&mut self) -> Result<(u32, Range<usize>), ParseError> {
+        match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Number(digits), span, .. }) => {
+                self.next += 1;
+                match self.convert(digits, span.clone())? {
+                    0 => Err(ParseError::ZeroCount { span }),
+                    count => Ok((count, span)),
+                }
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat("+") {
+                Op::Add
+            } else if self.eat("-") {
+                Op::Sub
+            } else {
+                return Ok(lhs);
+            };
+            self.node(self.last_span())?;
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.factor()?;
+        while self.eat("*") {
+            self.node(self.last_span())?;
+            let rhs = self.factor()?;
+            lhs = Expr::Binary(Op::Mul, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        // counting parentheses as well keeps deep nesting from overflowing
+        // the stack
+        let span = self.peek().map_or(self.src.len()..self.src.len(), |t| t.span.clone());
+        self.node(span)?;
+        if self.eat("(") {
+            let inner = self.expr()?;
+            if !self.eat(")") {
+                return Err(self.unexpected());
+            }
+            return Ok(inner);
+        }
+
+        match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Word("d"), .. }) => {
+                let roll = self.dice(1)?;
+                self.pool(roll)
+            }
+            Some(Token { kind: TokenKind::Number(digits), span, .. }) => {
+                self.next += 1;
+                if !self.attached_word("d") {
+                    return Ok(Expr::Constant(self.convert(digits, span)?));
+                }
+                let roll = self.dice(self.convert(digits, span)?)?;
+                self.pool(roll)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod parse_test {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    /// Number of random inputs tried by each property.
+    const CASES: usize = 2000;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb])
+    }
+
+    fn pick<'a, R: Rng>(rng: &mut R, choices: &[&'a str]) -> &'a str {
+        choices[rng.gen_range(0, choices.len())]
+    }
+
+    fn gen_number<R: Rng>(rng: &mut R, max: u32) -> String {
+        rng.gen_range(1, max + 1).to_string()
+    }
+
+    fn gen_compare<R: Rng>(rng: &mut R) -> String {
+        let sign = if rng.gen_weighted_bool(4) { "-" } else { "" };
+        format!("{}{}{}", pick(rng, &["=", "<", "<=", ">", ">="]), sign, gen_number(rng, 20))
+    }
+
+    fn gen_target<R: Rng>(rng: &mut R) -> String {
+        if rng.gen() { gen_compare(rng) } else { gen_number(rng, 20) }
+    }
+
+    fn gen_faces<R: Rng>(rng: &mut R) -> String {
+        let mut labels = Vec::new();
+        match rng.gen_range(0, 5) {
+            0 => return "F".to_string(),
+            1 => return "%".to_string(),
+            2 => for _ in 0..rng.gen_range(1, 6) {
+                labels.push(format!("{}{}", pick(rng, &["", "-"]), rng.gen_range(0, 9)));
+            },
+            3 => for _ in 0..rng.gen_range(1, 6) {
+                labels.push(pick(rng, &["hit", "miss", "crit", "x_2"]).to_string());
+            },
+            _ => return gen_number(rng, 100),
+        }
+        format!("{{{}}}", labels.join(","))
+    }
+
+    /// Generates a dice term. An explosion ending a pool always gets a
+    /// trigger, as the comparison of the pool would be its trigger otherwise.
+    fn gen_dice<R: Rng>(rng: &mut R, pool: bool) -> String {
+        let count = if rng.gen_weighted_bool(4) { String::new() } else { gen_number(rng, 20) };
+        let mut s = format!("{}d{}", count, gen_faces(rng));
+        let mut modifiers = vec![0, 1, 2];
+        rng.shuffle(&mut modifiers);
+        let mut bare_explosion = false;
+        for modifier in modifiers {
+            if rng.gen() {
+                continue;
+            }
+            bare_explosion = false;
+            match modifier {
+                0 => s += &format!("{}{}", pick(rng, &["r", "ro"]), gen_target(rng)),
+                1 => {
+                    s += pick(rng, &["!", "!!", "!p"]);
+                    if rng.gen() {
+                        s += &gen_target(rng);
+                    } else {
+                        bare_explosion = true;
+                    }
+                }
+                _ => {
+                    s += pick(rng, &["kh", "kl", "k", "dh", "dl"]);
+                    s += &gen_number(rng, 5);
+                }
+            }
+        }
+        if pool && bare_explosion {
+            s += &gen_target(rng);
+        }
+        s
+    }
+
+    fn gen_pool<R: Rng>(rng: &mut R) -> String {
+        let mut s = gen_dice(rng, true) + &gen_compare(rng);
+        if rng.gen() {
+            s += &format!("f{}", gen_target(rng));
+        }
+        s
+    }
+
+    fn gen_expr<R: Rng>(rng: &mut R, depth: u32) -> String {
+        let choice = if depth == 0 { rng.gen_range(0, 3) } else { rng.gen_range(0, 5) };
+        match choice {
+            0 => gen_dice(rng, false),
+            1 => gen_pool(rng),
+            2 => gen_number(rng, 50),
+            3 => format!("({})", gen_expr(rng, depth - 1)),
+            _ => {
+                let space = pick(rng, &["", " "]);
+                let op = pick(rng, &["+", "-", "*"]);
+                let lhs = gen_expr(rng, depth - 1);
+                format!("{}{}{}{}{}", lhs, space, op, space, gen_expr(rng, depth - 1))
+            }
+        }
+    }
+
+    /// Parses `s`, then checks that parsing its Display gives the same value.
+    fn assert_round_trip<T>(s: &str, parse: fn(&str) -> Result<T, ParseError>)
+    where
+        T: ::std::fmt::Display + ::std::fmt::Debug + PartialEq,
+    {
+        let parsed = parse(s).unwrap_or_else(|e| panic!("{:?} failed: {}", s, e.render(s)));
+        let shown = parsed.to_string();
+        let reparsed = parse(&shown).unwrap_or_else(|e| panic!("{:?} failed: {}", shown, e));
+        assert_eq!(parsed, reparsed, "{:?} was displayed as {:?}", s, shown);
+    }
+
+    #[test]
+    fn roll_commands_round_trip() {
+        let mut rng = rng();
+        for _ in 0..CASES {
+            assert_round_trip(&gen_dice(&mut rng, false), |s| roll_command(s, &Limits::default()));
+        }
+    }
+
+    #[test]
+    fn pool_commands_round_trip() {
+        let mut rng = rng();
+        for _ in 0..CASES {
+            assert_round_trip(&gen_pool(&mut rng), |s| pool_command(s, &Limits::default()));
+        }
+    }
+
+    #[test]
+    fn expressions_round_trip() {
+        let mut rng = rng();
+        for _ in 0..CASES {
+            assert_round_trip(&gen_expr(&mut rng, 3), |s| expression(s, &Limits::default()));
+        }
+    }
+
+    #[test]
+    fn pools_display_as_written() {
+        for s in &["10d10>=8f1", "6d6=6f<2", "4dF>0f=-1"] {
+            assert_eq!(*s, pool_command(s, &Limits::default()).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn pool_explosions_survive_display() {
+        let pool = pool_command("8d10!r1>=7", &Limits::default()).unwrap();
+        assert_eq!("8d10r1!=10>=7", pool.to_string());
+        assert_round_trip("8d10!r1>=7", |s| pool_command(s, &Limits::default()));
+        assert_round_trip("3d{-1,-2}!!=-1>=-1", |s| pool_command(s, &Limits::default()));
+        assert_round_trip("4dF!pro=-1<0", |s| pool_command(s, &Limits::default()));
+    }
+
+    #[test]
+    fn parentheses_survive_display() {
+        assert_round_trip("1 + (2d6 + 3)", |s| expression(s, &Limits::default()));
+        assert_round_trip("2 * (3 * d4)", |s| expression(s, &Limits::default()));
+        assert_round_trip("10 - (d6 - 1)", |s| expression(s, &Limits::default()));
+    }
+
+    #[test]
+    fn random_input_parses_whole_or_fails() {
+        let alphabet: Vec<char> = "0123456789dFkhlrop!<>=+-*(){},% fx".chars().collect();
+        let mut rng = rng();
+        for _ in 0..CASES * 5 {
+            let len = rng.gen_range(1, 12);
+            let s: String = (0..len).map(|_| *rng.choose(&alphabet).unwrap()).collect();
+            if expression(&s, &Limits::default()).is_ok() {
+                assert_round_trip(&s, |s| expression(s, &Limits::default()));
+            }
+        }
+    }
+
+    #[test]
+    fn repeats_need_dice() {
+        for s in &["2x6", "6x20", "repeat(6, 20)", "3x(2 + 1)"] {
+            let err = repeat(s, &Limits::default()).unwrap_err();
+            assert!(matches!(err, ParseError::MissingDice { .. }), "{:?} gave {:?}", s, err);
+        }
+        let err = repeat("2x6", &Limits::default()).unwrap_err();
+        assert_eq!(ParseError::MissingDice { span: 2..3 }, err);
+        assert_eq!(1, repeat("20", &Limits::default()).unwrap().count());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for s in &["2d6d8", "d", "2x6", "-3", "2dd6", "foo6", "2 d6", "d 6", "4d6 kh3", "2d6!!!"] {
+            assert!(expression(s, &Limits::default()).is_err(), "{:?} should not parse", s);
+            assert!(roll_command(s, &Limits::default()).is_err(), "{:?} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Success-counting dice pools such as `8d10>=7` or `10d10>=8f1`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use distribution::{self, Count, Distribution};
+use error::{ParseError, RollError};
+use limits::{Budget, Limits};
+use source::DieSource;
+use parse;
+use {Compare, ExplodeMode, RollCommand, RollResult};
+
+/// Rolls a pool of dice and counts the faces that hit a target number.
+///
+/// ** Parameters **
+/// - Roll: the dice making up the pool
+/// - Success: the faces that count as a success
+/// - Failure: the faces that cancel out a success, if any
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct PoolCommand {
+    roll: RollCommand,
+    success: Compare,
+    failure: Option<Compare>,
+}
+
+impl PoolCommand {
+    /// Constructs a new PoolCommand counting the faces that match `success`.
+    pub fn new(roll: RollCommand, success: Compare) -> PoolCommand {
+        PoolCommand { roll, success, failure: None }
+    }
+
+    /// Returns this pool with faces matching `failure` subtracted as failures.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Compare, PoolCommand, RollCommand};
+    ///
+    /// let pool = PoolCommand::new(RollCommand::new(10, 10), Compare::Ge(8));
+    /// let pool = pool.failure(Compare::Eq(1));
+    /// assert!(pool == "10d10>=8f1".parse().unwrap());
+    /// ```
+    pub fn failure(self, failure: Compare) -> PoolCommand {
+        PoolCommand { failure: Some(failure), ..self }
+    }
+
+    /// Parses a pool command like `str::parse`, but within the given limits
+    /// instead of the default ones.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<PoolCommand, ParseError> {
+        parse::pool_command(s, limits)
+    }
+
+    /// Rolls the pool and counts its successes.
+    ///
+    /// `source` is used the same way as by `RollCommand::result`. Dropped
+    /// dice are not counted. Every roll of an exploding die is counted on
+    /// its own, unless the explosion compounds.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::PoolCommand;
+    ///
+    /// let mut rng = [10, 7, 3, 1].iter();
+    /// let pool: PoolCommand = "4d10>=7f1".parse().unwrap();
+    /// let result = pool.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(2 == result.successes());
+    /// assert!(1 == result.failures());
+    /// assert!(1 == result.total());
+    /// ```
+    pub fn result<S>(&self, source: &mut S) -> Result<PoolResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.result_with(source, &Limits::default())
+    }
+
+    /// Rolls the pool like `result`, but within the given limits instead of
+    /// the default ones.
+    pub fn result_with<S>(&self, source: &mut S, limits: &Limits) -> Result<PoolResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.eval(source, &mut Budget::new(limits))
+    }
+
+    /// Computes the exact chance of every number of successes, less
+    /// failures, within the default `Limits`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::PoolCommand;
+    ///
+    /// let pool: PoolCommand = "2d6>=5".parse().unwrap();
+    /// let dist = pool.distribution().unwrap();
+    /// assert!((dist.probability(2) - 1.0 / 9.0).abs() < 1e-9);
+    /// ```
+    pub fn distribution(&self) -> Result<Distribution, RollError> {
+        self.distribution_with(&Limits::default())
+    }
+
+    /// Computes the distribution like `distribution`, but within the given
+    /// limits instead of the default ones.
+    pub fn distribution_with(&self, limits: &Limits) -> Result<Distribution, RollError> {
+        self.pmf(&mut Budget::new(limits))
+    }
+
+    pub(crate) fn pmf(&self, budget: &mut Budget) -> Result<Distribution, RollError> {
+        distribution::roll(&self.roll, &Count::Successes(self.success, self.failure), budget)
+    }
+
+    pub(crate) fn eval<S>(
+        &self,
+        source: &mut S,
+        budget: &mut Budget,
+    ) -> Result<PoolResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        let roll = self.roll.eval(source, budget)?;
+        let compound = self.roll.explode.map(|e| e.mode()) == Some(ExplodeMode::Compound);
+
+        let mut faces = Vec::new();
+        for die in roll.iter().filter(|d| d.is_kept()) {
+            if compound {
+                faces.push(die.value());
+            } else {
+                faces.push(i128::from(die.face()));
+                faces.extend(die.explosions().iter().map(|&n| i128::from(n)));
+            }
+        }
+
+        let count = |on: Compare| faces.iter().filter(|&&face| on.matches(face)).count() as u32;
+        let successes = count(self.success);
+        let failures = self.failure.map_or(0, count);
+        Ok(PoolResult { roll, successes, failures })
+    }
+}
+
+impl fmt::Display for PoolCommand {
+    /// Writes the pool in dice notation: "10d10>=8f1"
+    ///
+    /// An explosion right before the success comparison is written with its
+    /// trigger, "8d10!=10>=7", as the comparison would be taken as the
+    /// trigger otherwise.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.roll.explode {
+            Some(explode) if self.roll.keep.is_none() => {
+                let explode = Some(explode.explicit(&self.roll.faces));
+                write!(f, "{}", RollCommand { explode, ..self.roll.clone() })?;
+            }
+            _ => write!(f, "{}", self.roll)?,
+        }
+        write!(f, "{}", self.success)?;
+        match self.failure {
+            Some(Compare::Eq(n)) if n >= 0 => write!(f, "f{}", n),
+            Some(failure) => write!(f, "f{}", failure),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts a string pool command to a pool command struct, within the
+/// default `Limits`.
+///
+/// 8d10>=7 => PoolCommand {roll: 8d10, success: Ge(7), failure: None}, etc
+impl FromStr for PoolCommand {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<PoolCommand, <PoolCommand as FromStr>::Err> {
+        parse::pool_command(s, &Limits::default())
+    }
+}
+
+/// The outcome of a PoolCommand.
+///
+/// Keeps the dice that were rolled next to the number of successes and
+/// failures among them.
+pub struct PoolResult {
+    roll: RollResult,
+    successes: u32,
+    failures: u32,
+}
+
+impl PoolResult {
+    /// Returns the dice that were rolled.
+    pub fn roll(&self) -> &RollResult {
+        &self.roll
+    }
+
+    pub fn successes(&self) -> u32 {
+        self.successes
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns the number of successes minus the number of failures.
+    ///
+    /// This goes below zero when failures outnumber successes, which is
+    /// how most pool systems detect a botch.
+    pub fn total(&self) -> i64 {
+        i64::from(self.successes) - i64::from(self.failures)
+    }
+}
+
+/// Serializes the dice of a pool along with its successes, failures and
+/// total.
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for PoolResult {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("PoolResult", 4)?;
+        state.serialize_field("roll", &self.roll)?;
+        state.serialize_field("successes", &self.successes)?;
+        state.serialize_field("failures", &self.failures)?;
+        state.serialize_field("total", &self.total())?;
+        state.end()
+    }
+}
+
+impl fmt::Display for PoolResult {
+    /// Implements Display for PoolResult.
+    ///
+    /// # Examples
+    /// [10, 7, 3] counting >=7 => "10, 7, 3 (2 successes)"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let noun = if self.total() == 1 { "success" } else { "successes" };
+        self.roll.fmt_dice(f)?;
+        write!(f, " ({} {})", self.total(), noun)
+    }
+}
+
+#[cfg(test)]
+mod pool_test {
+    use super::*;
+    use {Compare, RollCommand};
+
+    #[test]
+    fn can_parse_pools() {
+        let pool = PoolCommand::new(RollCommand::new(8, 10), Compare::Ge(7));
+        assert!(pool == "8d10>=7".parse().unwrap());
+        let pool = PoolCommand::new(RollCommand::new(6, 6), Compare::Eq(6));
+        assert!(pool == "6d6=6".parse().unwrap());
+        assert!("8d10".parse::<PoolCommand>().is_err());
+        assert!("8d10>=7f".parse::<PoolCommand>().is_err());
+    }
+
+    #[test]
+    fn failures_can_cause_a_botch() {
+        let mut rng = [1, 1, 9].iter();
+        let pool: PoolCommand = "3d10>=8f1".parse().unwrap();
+        let result = pool.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(-1 == result.total());
+        assert!("1, 1, 9 (-1 successes)" == result.to_string());
+    }
+
+    #[test]
+    fn exploded_faces_count_separately() {
+        let mut rng = [10, 8, 2].iter();
+        let pool: PoolCommand = "2d10!10>=8".parse().unwrap();
+        let result = pool.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(2 == result.successes());
+
+        let mut rng = [10, 8, 2].iter();
+        let pool: PoolCommand = "2d10!!10>=8".parse().unwrap();
+        let result = pool.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(1 == result.successes());
+    }
+}
@@ -0,0 +1,219 @@
+//! Questions about the chance of a roll, such as `2d6 + 3 >= 10` or the
+//! opposed roll `1d20 + 5 > 1d20 + 3`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use error::{ParseError, RollError};
+use limits::{Budget, Limit, Limits};
+use parse;
+use {Compare, Expr};
+
+/// How the totals on both sides of a `Query` are compared.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Relation {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Relation {
+    /// Returns the comparison of a total against `n` in this relation.
+    pub fn against(self, n: i64) -> Compare {
+        match self {
+            Relation::Eq => Compare::Eq(n),
+            Relation::Lt => Compare::Lt(n),
+            Relation::Le => Compare::Le(n),
+            Relation::Gt => Compare::Gt(n),
+            Relation::Ge => Compare::Ge(n),
+        }
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Relation::Eq => "=",
+            Relation::Lt => "<",
+            Relation::Le => "<=",
+            Relation::Gt => ">",
+            Relation::Ge => ">=",
+        })
+    }
+}
+
+/// Compares the totals of two expressions rolled independently.
+///
+/// Either side may be a plain number, which makes a target to roll
+/// against, or another roll, which makes an opposed roll. A lone number is
+/// a constant here, not the shorthand for a die it is in an `Expr`.
+///
+/// The comparison needs whitespace around it when it follows dice, since
+/// `8d10>=7` is a success-counting pool.
+///
+/// # Examples
+/// ```
+/// use rcmd::Query;
+///
+/// let query: Query = "2d6 + 3 >= 10".parse().unwrap();
+/// assert!((query.chance().unwrap() - 21.0 / 36.0).abs() < 1e-9);
+///
+/// let opposed: Query = "1d20 + 5 > 1d20 + 3".parse().unwrap();
+/// assert!((opposed.chance().unwrap() - 229.0 / 400.0).abs() < 1e-9);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Query {
+    lhs: Expr,
+    relation: Relation,
+    rhs: Expr,
+}
+
+impl Query {
+    pub fn new(lhs: Expr, relation: Relation, rhs: Expr) -> Query {
+        Query { lhs, relation, rhs }
+    }
+
+    /// Parses a query like `str::parse`, but within the given limits
+    /// instead of the default ones.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<Query, ParseError> {
+        parse::query(s, limits)
+    }
+
+    pub fn lhs(&self) -> &Expr {
+        &self.lhs
+    }
+
+    pub fn relation(&self) -> Relation {
+        self.relation
+    }
+
+    pub fn rhs(&self) -> &Expr {
+        &self.rhs
+    }
+
+    /// Computes the exact chance that the query holds, within the default
+    /// `Limits`.
+    pub fn chance(&self) -> Result<f64, RollError> {
+        self.chance_with(&Limits::default())
+    }
+
+    /// Computes the chance like `chance`, but within the given limits
+    /// instead of the default ones.
+    pub fn chance_with(&self, limits: &Limits) -> Result<f64, RollError> {
+        if self.lhs.nodes() + self.rhs.nodes() > limits.nodes {
+            return Err(RollError::LimitExceeded(Limit::Nodes(limits.nodes)));
+        }
+        let mut budget = Budget::new(limits);
+        let lhs = self.lhs.pmf(&mut budget)?;
+        let rhs = self.rhs.pmf(&mut budget)?;
+        let difference = lhs.convolve(&rhs.negate()?, &mut budget)?;
+        Ok(difference.chance(self.relation.against(0)))
+    }
+
+    /// Computes the damage an attack deals on average, if it hits whenever
+    /// the query holds and then deals `damage`.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Expr, Query};
+    ///
+    /// let attack: Query = "1d20 + 5 >= 15".parse().unwrap();
+    /// let damage: Expr = "2d6 + 3".parse().unwrap();
+    /// // hits 55% of the time for 10 damage on average
+    /// assert!((attack.expected_damage(&damage).unwrap() - 5.5).abs() < 1e-9);
+    /// ```
+    pub fn expected_damage(&self, damage: &Expr) -> Result<f64, RollError> {
+        self.expected_damage_with(damage, &Limits::default())
+    }
+
+    /// Computes the damage like `expected_damage`, but within the given
+    /// limits instead of the default ones. The chance and the damage are
+    /// held to the limits separately.
+    pub fn expected_damage_with(&self, damage: &Expr, limits: &Limits) -> Result<f64, RollError> {
+        Ok(self.chance_with(limits)? * damage.distribution_with(limits)?.mean())
+    }
+}
+
+impl fmt::Display for Query {
+    /// Writes the query in dice notation: "1d20 + 5 >= 15"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.relation, self.rhs)
+    }
+}
+
+/// Converts a string query to a query struct, within the default `Limits`.
+///
+/// "1d20 + 5 >= 15" => Query {lhs: 1d20 + 5, relation: Ge, rhs: 15}, etc
+impl FromStr for Query {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Query, <Query as FromStr>::Err> {
+        parse::query(s, &Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod query_test {
+    use super::*;
+
+    fn chance(s: &str) -> f64 {
+        s.parse::<Query>().unwrap().chance().unwrap()
+    }
+
+    fn close(expected: f64, actual: f64) -> bool {
+        (expected - actual).abs() < 1e-9
+    }
+
+    #[test]
+    fn can_parse_queries() {
+        let query: Query = "1d20+5>=15".parse().unwrap();
+        assert_eq!(Relation::Ge, query.relation());
+        assert_eq!(Expr::Constant(15), *query.rhs());
+        assert_eq!("1d20 + 5 >= 15", query.to_string());
+        assert_eq!(query, query.to_string().parse().unwrap());
+
+        // a comparison attached to dice belongs to a pool
+        let query: Query = "8d10>=7 > 2".parse().unwrap();
+        assert!(matches!(*query.lhs(), Expr::Pool(_)));
+
+        assert!("8d10>=7".parse::<Query>().is_err());
+        assert!("1d20 >=".parse::<Query>().is_err());
+        assert!("1d20 >= 5 >= 3".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn chances_against_targets() {
+        assert!(close(0.55, chance("1d20 + 5 >= 15")));
+        assert!(close(0.5, chance("1d20 + 5 > 15")));
+        assert!(close(0.05, chance("1d20 = 20")));
+        assert!(close(1.0 / 36.0, chance("2d6 < 3")));
+        assert!(close(1.0, chance("1d6 <= 6")));
+        assert!(close(0.0, chance("1d6 > 6")));
+        assert!(close(0.3f64.powi(2) * 3.0 * 0.7 + 0.3f64.powi(3), chance("3d10>=8 >= 2")));
+    }
+
+    #[test]
+    fn opposed_rolls() {
+        assert!(close(15.0 / 36.0, chance("1d6 > 1d6")));
+        assert!(close(1.0 / 6.0, chance("1d6 = 1d6")));
+        assert!(close(chance("1d20 + 2 >= 1d20"), 1.0 - chance("1d20 + 2 < 1d20")));
+        assert!(close(1.0, chance("1d4 + 4 > 1d4")));
+    }
+
+    #[test]
+    fn expected_damage() {
+        let attack: Query = "1d20 >= 11".parse().unwrap();
+        let damage: Expr = "1d8 + 2".parse().unwrap();
+        assert!(close(3.25, attack.expected_damage(&damage).unwrap()));
+
+        let limits = Limits::default().total_dice(4);
+        assert!(close(3.25, attack.expected_damage_with(&damage, &limits).unwrap()));
+        let damage: Expr = "8d6".parse().unwrap();
+        let err = attack.expected_damage_with(&damage, &limits).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::TotalDice(4))), err);
+    }
+}
@@ -1,37 +1,183 @@
-#![feature(slice_patterns)]
+extern crate hmac;
+extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate sha2;
+
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+use distribution::Count;
+use limits::Budget;
+
+mod lex;
+mod parse;
+pub mod distribution;
+pub mod error;
+pub mod estimate;
+pub mod expr;
+pub mod fair;
+pub mod faces;
+pub mod limits;
+pub mod modifier;
+pub mod pool;
+pub mod query;
+pub mod repeat;
+pub mod source;
+pub mod transcript;
+
+pub use distribution::{Distribution, Table};
+pub use error::{FairError, ParseError, ReplayError, RollError};
+pub use estimate::{Estimate, Estimator};
+pub use expr::{Expr, ExprResult};
+pub use fair::{Commitment, FairRoller, FairSource, ServerSeed};
+pub use faces::Faces;
+pub use limits::{Limit, Limits};
+pub use modifier::{Compare, Explode, ExplodeMode, Keep, Reroll};
+pub use pool::{PoolCommand, PoolResult};
+pub use query::{Query, Relation};
+pub use repeat::{Order, Repeat, RepeatResult};
+pub use source::{sample_side, DieSource, OsSource, Recorder, Replay, SeededSource};
+pub use source::{SequenceSource, WordSource};
+pub use transcript::RollLog;
+
 /// Stores roll parameters.
 /// 
 /// ** Parameters **
 /// - Count: the number of dice to be rolled
-/// - Range: the highest value on each dice
-#[derive(Eq, PartialEq)]
+/// - Faces: the faces on each dice, numbered from 1 to a range by default
+/// - Reroll: which faces are rolled again before anything else happens
+/// - Explode: when to roll extra dice for a die
+/// - Keep: which of the rolled dice count towards the total
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct RollCommand {
     count: u32, // unsigned, 32bit integer 
-    range: u32, 
+    faces: Faces, 
+    reroll: Option<Reroll>,
+    explode: Option<Explode>,
+    keep: Option<Keep>,
 }
 
 impl RollCommand {
     /// Constructs a new RollCommand with basic parameters.
+    ///
+    /// The parameters are not checked; rolling a command without dice or
+    /// sides fails. Use `try_new` to find out up front.
     pub fn new(c: u32, r: u32) -> RollCommand {
-        RollCommand { count: c, range: r }
+        RollCommand::with_faces(c, Faces::Numbered(r))
+    }
+
+    /// Constructs a new RollCommand, failing if it has no dice or no sides.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{RollCommand, RollError};
+    ///
+    /// assert!(RollCommand::try_new(2, 6) == Ok(RollCommand::new(2, 6)));
+    /// assert!(RollCommand::try_new(0, 6) == Err(RollError::ZeroCount));
+    /// assert!(RollCommand::try_new(1, 0) == Err(RollError::ZeroSides));
+    /// ```
+    pub fn try_new(c: u32, r: u32) -> Result<RollCommand, RollError> {
+        RollCommand::try_with_faces(c, Faces::Numbered(r))
+    }
+
+    /// Constructs a new RollCommand for dice with arbitrary faces.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Faces, RollCommand};
+    ///
+    /// let cmd = RollCommand::with_faces(4, Faces::Fate);
+    /// assert!(cmd == "4dF".parse().unwrap());
+    /// ```
+    pub fn with_faces(c: u32, faces: Faces) -> RollCommand {
+        RollCommand { count: c, faces, reroll: None, explode: None, keep: None }
+    }
+
+    /// Constructs a new RollCommand for dice with arbitrary faces, failing
+    /// if it has no dice or no sides.
+    pub fn try_with_faces(c: u32, faces: Faces) -> Result<RollCommand, RollError> {
+        let cmd = RollCommand::with_faces(c, faces);
+        cmd.check()?;
+        Ok(cmd)
+    }
+
+    fn check(&self) -> Result<(), RollError> {
+        if self.count == 0 {
+            Err(RollError::ZeroCount)
+        } else if self.faces.sides() == 0 {
+            Err(RollError::ZeroSides)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parses a roll command like `str::parse`, but within the given limits
+    /// instead of the default ones.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<RollCommand, ParseError> {
+        parse::roll_command(s, limits)
+    }
+
+    /// Returns this command with a reroll modifier applied.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Compare, Reroll, RollCommand};
+    ///
+    /// let cmd = RollCommand::new(2, 6).reroll(Reroll::once(Compare::Le(2)));
+    /// assert!(cmd == "2d6ro<=2".parse().unwrap());
+    /// ```
+    pub fn reroll(self, reroll: Reroll) -> RollCommand {
+        RollCommand { reroll: Some(reroll), ..self }
+    }
+
+    /// Returns this command with exploding dice.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Compare, Explode, ExplodeMode, RollCommand};
+    ///
+    /// let explode = Explode::new(ExplodeMode::Explode).on(Compare::Ge(5));
+    /// let cmd = RollCommand::new(1, 6).explode(explode);
+    /// assert!(cmd == "d6!>=5".parse().unwrap());
+    /// ```
+    pub fn explode(self, explode: Explode) -> RollCommand {
+        RollCommand { explode: Some(explode), ..self }
+    }
+
+    /// Returns this command with a keep or drop modifier applied.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Keep, RollCommand};
+    ///
+    /// let cmd = RollCommand::new(4, 6).keep(Keep::Highest(3));
+    /// assert!(cmd == "4d6kh3".parse().unwrap());
+    /// ```
+    pub fn keep(self, keep: Keep) -> RollCommand {
+        RollCommand { keep: Some(keep), ..self }
     }
 
     /// Generates a RollResult based on a command.
     ///
     /// Each command can be used any number of times; this function will
     /// generate new results each time.
-    /// Higher order function -> up to the caller to provide an appropriate
-    /// function to generate random values, any function will be used
+    /// Up to the caller to provide an appropriate source of random values,
+    /// see `DieSource`; any function will be used as long as it returns a
+    /// side between 1 and the `max` it is given.
+    ///
+    /// Fails if the command has no dice or no sides, if the source fails, or
+    /// if it returns a side the die does not have.
     ///
     /// # Examples
     /// ```
     /// use rcmd::RollCommand;
     /// 
     /// let cmd = RollCommand::new(2, 6);
-    /// let result = cmd.result(|max| max);
-    /// assert!([6, 6] == result.values());
+    /// let result = cmd.result(&mut |max| max).unwrap();
+    /// assert!(result.values() == [6, 6]);
     /// ```
     /// 
     /// Here we have a function that provides values from an iterator instead
@@ -43,67 +189,380 @@ impl RollCommand {
     /// let rng_src = [1,2,3,4];
     /// let mut rng = rng_src.iter();
     /// let cmd = RollCommand::new(4, 6);
-    /// let result = cmd.result(|_| *rng.next().unwrap());
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(result.values() == [1,2,3,4]);
+    /// ```
+    ///
+    /// Dice removed by a keep or drop modifier stay in the result, but no
+    /// longer count towards the total:
     ///
-    /// assert!([1,2,3,4] == result.values());
     /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let mut rng = [3,1,6,4].iter();
+    /// let cmd: RollCommand = "4d6dl1".parse().unwrap();
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(result.values() == [3,1,6,4]);
+    /// assert!(result.kept() == [3,6,4]);
+    /// assert!(13 == result.total());
+    /// ```
+    ///
+    /// Exploding dice record the extra rolls they triggered:
+    ///
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let mut rng = [6,6,2].iter();
+    /// let cmd: RollCommand = "d6!".parse().unwrap();
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(result.iter().next().unwrap().explosions() == [6, 2]);
+    /// assert!(14 == result.total());
+    /// ```
+    ///
+    /// Rerolled dice keep the faces they discarded:
+    ///
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let mut rng = [1,1,4].iter();
+    /// let cmd: RollCommand = "d6r1".parse().unwrap();
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(result.iter().next().unwrap().rerolled() == [1, 1]);
+    /// assert!(4 == result.total());
+    /// ```
+    ///
+    /// For dice that are not numbered from 1, the source still picks a side between
+    /// 1 and the number of sides, which is then turned into its face:
+    ///
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let mut rng = [1,2,3,3].iter();
+    /// let cmd: RollCommand = "4dF".parse().unwrap();
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// assert!(result.values() == [-1, 0, 1, 1]);
+    /// assert!("-, 0, +, + (1)" == result.to_string());
+    /// ```
+    ///
+    /// A side outside of the die is an error rather than a bogus face:
+    ///
+    /// ```
+    /// use rcmd::{RollCommand, RollError};
+    ///
+    /// let err = RollCommand::new(1, 6).result(&mut |_| 7).err();
+    /// assert!(Some(RollError::FaceOutOfRange { side: 7, sides: 6 }) == err);
+    /// ```
+    pub fn result<S>(&self, source: &mut S) -> Result<RollResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.result_with(source, &Limits::default())
+    }
+
+    /// Generates a RollResult like `result`, but within the given limits
+    /// instead of the default ones.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Limit, Limits, RollCommand, RollError};
+    ///
+    /// let limits = Limits::default().dice(100);
+    /// let err = RollCommand::new(1000, 6).result_with(&mut |max| max, &limits).err();
+    /// assert!(Some(RollError::LimitExceeded(Limit::Dice(100))) == err);
+    /// ```
+    pub fn result_with<S>(&self, source: &mut S, limits: &Limits) -> Result<RollResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.eval(source, &mut Budget::new(limits))
+    }
+
+    /// Computes the exact chance of every total this command can roll,
+    /// within the default `Limits`.
+    ///
+    /// Explosions are followed as deep as the explosion depth limit allows.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let cmd: RollCommand = "4d6kh3".parse().unwrap();
+    /// let dist = cmd.distribution().unwrap();
+    /// assert!(3 == dist.min() && 18 == dist.max());
+    /// assert!(dist.mean() > 12.24 && dist.mean() < 12.25);
+    /// ```
+    pub fn distribution(&self) -> Result<Distribution, RollError> {
+        self.distribution_with(&Limits::default())
+    }
+
+    /// Computes the distribution like `distribution`, but within the given
+    /// limits instead of the default ones.
+    pub fn distribution_with(&self, limits: &Limits) -> Result<Distribution, RollError> {
+        self.pmf(&mut Budget::new(limits))
+    }
+
+    pub(crate) fn pmf(&self, budget: &mut Budget) -> Result<Distribution, RollError> {
+        distribution::roll(self, &Count::Sum, budget)
+    }
+
+    pub(crate) fn eval<S>(
+        &self,
+        source: &mut S,
+        budget: &mut Budget,
+    ) -> Result<RollResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.check()?;
+        budget.dice(self.count, self.faces.sides())?;
+        let depth = budget.limits.explosion_depth;
+        let mut pick = |sides| {
+            budget.step()?;
+            source.pick(sides)
+        };
+
+        let mut dice = Vec::new();
+        for _ in 0..self.count {
+            let mut face = self.faces.roll(&mut pick)?;
+            let rerolled = match self.reroll {
+                Some(ref reroll) => reroll.apply(&mut face, &self.faces, &mut pick)?,
+                None => Vec::new(),
+            };
+            let explosions = match self.explode {
+                Some(ref explode) => explode.chain(face, &self.faces, &mut pick, depth)?,
+                None => Vec::new(),
+            };
+            dice.push(Die { face, kept: true, rerolled, explosions });
+        }
+        if let Some(keep) = self.keep {
+            keep.apply(&mut dice);
+        }
+        Ok(RollResult { faces: self.faces.clone(), dice })
+    }
+}
 
-    pub fn result<F: FnMut(u32) -> u32>(&self, mut f: F) -> RollResult {
-        RollResult((0..self.count).map(|_| f(self.range)).collect())
-        
-        /* alternative
-        let mut vec = Vec::new();
-        for _ in (0..self.count) {
-            vec.push(f(self.range));
+impl std::fmt::Display for RollCommand {
+    /// Writes the command in dice notation, so that parsing the output
+    /// gives back an equal command.
+    ///
+    /// ```
+    /// use rcmd::RollCommand;
+    /// let cmd: RollCommand = "4d6kh3r1".parse().unwrap();
+    /// assert!("4d6r1kh3" == cmd.to_string());
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}d{}", self.count, self.faces)?;
+        if let Some(reroll) = self.reroll {
+            write!(f, "{}", reroll)?;
+        }
+        if let Some(explode) = self.explode {
+            write!(f, "{}", explode)?;
         }
-        RollResult(vec)
-        */
+        if let Some(keep) = self.keep {
+            write!(f, "{}", keep)?;
+        }
+        Ok(())
     }
 }
 
-/// Converts a string roll command to a roll command struct.
+/// Converts a string roll command to a roll command struct, within the
+/// default `Limits`.
 /// 
-/// 2d6 => RollCommand {count: 2, range: 6}, etc
+/// 2d6 => RollCommand {count: 2, faces: Numbered(6)}, etc
 impl FromStr for RollCommand {
-    type Err = String;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<RollCommand, <RollCommand as FromStr>::Err> {
-        // 2d6: [2, 6]
-        let split: Vec<_> = s.split('d').filter_map(|n| n.parse().ok()).collect();
-        // match slice by splitting the string and return some valid result
-        match split[..] {
-            [ref count, ref range] => Ok(RollCommand::new(*count, *range)), 
-            [ref range] => Ok(RollCommand::new(1, *range)), 
-            _ => Err(format!("Invalid command: {}.", s)), 
+        parse::roll_command(s, &Limits::default())
+    }
+}
+
+/// Serializes the faces and dice of a roll along with its total, and the
+/// tally of symbol dice, whose total is always 0.
+#[cfg(feature = "serde")]
+impl serde::Serialize for RollResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("RollResult", 4)?;
+        state.serialize_field("faces", &self.faces)?;
+        state.serialize_field("dice", &self.dice)?;
+        state.serialize_field("total", &self.total())?;
+        if self.faces.is_symbolic() {
+            state.serialize_field("tally", &self.tally())?;
+        } else {
+            state.skip_field("tally")?;
         }
+        state.end()
+    }
+}
+
+/// A single die of a RollResult.
+///
+/// Remembers the face that was rolled, the faces it discarded by being
+/// rerolled, the chain of extra rolls it triggered by exploding and whether
+/// the die still counts towards the total after keep and drop modifiers
+/// were applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Die {
+    face: i64,
+    kept: bool,
+    rerolled: Vec<i64>,
+    explosions: Vec<i64>,
+}
+
+impl Die {
+    /// Returns the face that was rolled, after any rerolls.
+    pub fn face(&self) -> i64 {
+        self.face
+    }
+
+    /// Returns the faces discarded by rerolling, in the order they were rolled.
+    pub fn rerolled(&self) -> &[i64] {
+        &self.rerolled
+    }
+
+    /// Returns the values added by exploding, in the order they were rolled.
+    pub fn explosions(&self) -> &[i64] {
+        &self.explosions
+    }
+
+    /// Returns the face plus everything added by exploding.
+    ///
+    /// The value is widened to `i128`, so that no chain of explosions can
+    /// overflow it.
+    pub fn value(&self) -> i128 {
+        i128::from(self.face) + self.explosions.iter().map(|&n| i128::from(n)).sum::<i128>()
+    }
+
+    /// Returns false if the die was dropped by a keep or drop modifier.
+    pub fn is_kept(&self) -> bool {
+        self.kept
     }
 }
 
-/// RollResult is a vector of unsigned integers.
+/// RollResult is a vector of dice.
 ///
-/// RollResult wraps a vector of unsinged integers representing the result
-/// of a roll command once executed. Wrapping the vector allows us to provice
-/// specialised function implementations for dealing with roll results.
-pub struct RollResult(Vec<u32>);
+/// RollResult wraps a vector of dice representing the result of a roll
+/// command once executed, along with the faces they were rolled on.
+/// Wrapping the vector allows us to provice specialised function
+/// implementations for dealing with roll results.
+pub struct RollResult {
+    faces: Faces,
+    dice: Vec<Die>,
+}
 
 impl RollResult {
     /// Returns an iterator over the result of a roll.
     /// 
     /// This function actually just returns an iterator on the 
-    /// underlying vectory used to store the result values.
-    pub fn iter<'a>(&'a self) -> std::slice::Iter<'a, u32> {
-        self.0.iter()
+    /// underlying vectory used to store the dice, dropped ones included.
+    pub fn iter(&self) -> std::slice::Iter<'_, Die> {
+        self.dice.iter()
+    }
+
+    /// Returns the faces the dice were rolled on.
+    pub fn faces(&self) -> &Faces {
+        &self.faces
     }
 
     /// Returns the total value of the roll
     ///
-    /// This function folds over the internal vector of the RollResult to sum.
-    pub fn total(&self) -> u32 {
-        self.0.iter().fold(0, |a, b| a + b)
+    /// This function sums over the kept dice of the RollResult. Symbols do
+    /// not add up, so the total of symbol dice is always 0; see `tally`.
+    ///
+    /// The total is widened to `i128`, which holds the sum of far more
+    /// dice than any `Limits` let a single roll make, whatever their faces.
+    pub fn total(&self) -> i128 {
+        if self.faces.is_symbolic() {
+            return 0;
+        }
+        self.dice.iter().filter(|d| d.kept).map(Die::value).sum()
+    }
+
+    /// Counts how often each symbol came up on the kept dice.
+    ///
+    /// Symbols are listed in the order they are printed on the die, and
+    /// every roll of an exploding die is counted. Numbered dice are tallied
+    /// by value, lowest first.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCommand;
+    ///
+    /// let mut rng = [4,1,1].iter();
+    /// let cmd: RollCommand = "3d{hit,hit,miss,crit}".parse().unwrap();
+    /// let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+    ///
+    /// let tally = vec![("hit".to_string(), 2), ("crit".to_string(), 1)];
+    /// assert!(result.tally() == tally);
+    /// ```
+    pub fn tally(&self) -> Vec<(String, u32)> {
+        let mut counts = BTreeMap::new();
+        for die in self.dice.iter().filter(|d| d.kept) {
+            for &n in Some(&die.face).into_iter().chain(&die.explosions) {
+                *counts.entry(n).or_insert(0) += 1;
+            }
+        }
+
+        // the same symbol may be printed on several sides
+        let mut tally: Vec<(String, u32)> = Vec::new();
+        for (value, n) in counts {
+            let label = self.faces.label(value);
+            match tally.iter_mut().find(|entry| entry.0 == label) {
+                Some(entry) => entry.1 += n,
+                None => tally.push((label, n)),
+            }
+        }
+        tally
     }
 
-    pub fn values(&self) -> &[u32] {
-        &self.0
+    /// Returns the values of all dice in the order they were rolled.
+    pub fn values(&self) -> Vec<i128> {
+        self.dice.iter().map(Die::value).collect()
+    }
+
+    /// Returns the values of the dice that count towards the total.
+    pub fn kept(&self) -> Vec<i128> {
+        self.dice.iter().filter(|d| d.kept).map(Die::value).collect()
+    }
+
+    /// Returns the values of the dice removed by a keep or drop modifier.
+    pub fn dropped(&self) -> Vec<i128> {
+        self.dice.iter().filter(|d| !d.kept).map(Die::value).collect()
+    }
+
+    /// Renders a single die of this roll.
+    ///
+    /// Rerolled faces are followed by an r: 1, 4 => "1r4"
+    /// Explosion chains are joined with a plus: 6, 6, 2 => "6+6+2"
+    /// Dropped dice are struck through: 1 => "~~1~~"
+    fn die_to_string(&self, die: &Die) -> String {
+        let mut chain = String::new();
+        for &n in &die.rerolled {
+            chain = format!("{}{}r", chain, self.faces.label(n));
+        }
+        chain = format!("{}{}", chain, self.faces.label(die.face));
+        for &n in &die.explosions {
+            chain = format!("{}+{}", chain, self.faces.label(n));
+        }
+        if die.kept {
+            chain
+        } else {
+            format!("~~{}~~", chain)
+        }
+    }
+
+    /// Writes the dice of this roll separated by commas, without a total.
+    pub(crate) fn fmt_dice(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let as_strings: Vec<_> = self.dice.iter().map(|d| self.die_to_string(d)).collect();
+        write!(f, "{}", as_strings.join(", "))
     }
 }
 
@@ -112,22 +571,34 @@ impl std::fmt::Display for RollResult {
     /// 
     /// # Examples
     /// [1, 2, 3] => "1, 2, 3 (6)"
+    /// [1, 2, 3] keeping the highest two => "~~1~~, 2, 3 (5)"
+    /// [-1, 0, 1] on Fate dice => "-, 0, + (0)"
+    /// [hit, miss, hit] on symbol dice => "hit, miss, hit (2 hit, 1 miss)"
     /// 
     /// ```
-    /// use rcmd::RollResult;
-    /// let vec: vec![1,2,3];
-    /// let result = RollResult(vec);
+    /// use rcmd::RollCommand;
+    /// let mut rng = [1,2,3].iter();
+    /// let result = RollCommand::new(3, 6).result(&mut |_| *rng.next().unwrap()).unwrap();
     /// assert!("1, 2, 3 (6)" == result.to_string());
     /// ```
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let as_strings: Vec<_> = self.0.iter().map(|n| n.to_string()).collect();
-        write!(f, "{} ({})", as_strings.connect(", "), self.total())
+        self.fmt_dice(f)?;
+        if self.faces.is_symbolic() {
+            let counts: Vec<_> = self.tally()
+                .into_iter()
+                .map(|(label, n)| format!("{} {}", n, label))
+                .collect();
+            write!(f, " ({})", counts.join(", "))
+        } else {
+            write!(f, " ({})", self.total())
+        }
     }
 }
 
 #[cfg(test)]
 mod rollcommand_test {
     use super::*; // pulls in code from this mod
+    use modifier::{DEFAULT_EXPLOSION_DEPTH, DEFAULT_REROLL_DEPTH};
 
     #[test]
     fn can_parse_full_rollcommands() {
@@ -140,4 +611,226 @@ mod rollcommand_test {
         let cmd = RollCommand::new(1, 6);
         assert!(cmd == "6".parse().unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_trailing_input() {
+        assert!("2dd6".parse::<RollCommand>().is_err());
+        assert!("2d6d8".parse::<RollCommand>().is_err());
+        assert!("2d6 + 1".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dice_and_sides() {
+        let err = RollCommand::new(0, 6).result(&mut |max| max).err();
+        assert!(Some(RollError::ZeroCount) == err);
+        let err = RollCommand::new(1, 0).result(&mut |max| max).err();
+        assert!(Some(RollError::ZeroSides) == err);
+        let faces = Faces::Custom(Vec::new());
+        assert!(Err(RollError::ZeroSides) == RollCommand::try_with_faces(1, faces));
+        assert!("0d6".parse::<RollCommand>().is_err());
+        assert!("2d0".parse::<RollCommand>().is_err());
+        assert!("0".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn rejects_sides_out_of_range() {
+        let err = RollCommand::new(2, 6).result(&mut |_| 0).err();
+        assert!(Some(RollError::FaceOutOfRange { side: 0, sides: 6 }) == err);
+
+        let mut rng = [3, 4].iter();
+        let cmd: RollCommand = "dF!".parse().unwrap();
+        let err = cmd.result(&mut |_| *rng.next().unwrap()).err();
+        assert!(Some(RollError::FaceOutOfRange { side: 4, sides: 3 }) == err);
+    }
+
+    #[test]
+    fn can_parse_keep_and_drop() {
+        let cmd = RollCommand::new(4, 6);
+        assert!(cmd.clone().keep(Keep::Highest(3)) == "4d6kh3".parse().unwrap());
+        assert!(cmd.clone().keep(Keep::Highest(3)) == "4d6k3".parse().unwrap());
+        assert!(cmd.clone().keep(Keep::Lowest(1)) == "4d6kl1".parse().unwrap());
+        assert!(cmd.clone().keep(Keep::DropHighest(2)) == "4d6dh2".parse().unwrap());
+        assert!(cmd.keep(Keep::DropLowest(1)) == "4d6dl1".parse().unwrap());
+        assert!("4d6kh".parse::<RollCommand>().is_err());
+        assert!("4d6kh3kl1".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn keep_lowest_takes_disadvantage() {
+        let mut rng = [17, 4].iter();
+        let cmd = RollCommand::new(2, 20).keep(Keep::Lowest(1));
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(4 == result.total());
+        assert!(result.dropped() == [17]);
+        assert!("~~17~~, 4 (4)" == result.to_string());
+    }
+
+    #[test]
+    fn keep_breaks_ties_by_roll_order() {
+        let result = RollCommand::new(3, 6).keep(Keep::Highest(1)).result(&mut |_| 5).unwrap();
+        assert!("5, ~~5~~, ~~5~~ (5)" == result.to_string());
+    }
+
+    #[test]
+    fn can_parse_explosions() {
+        let cmd = RollCommand::new(2, 6);
+        let explode = Explode::new(ExplodeMode::Explode);
+        assert!(cmd.clone().explode(explode) == "2d6!".parse().unwrap());
+        let compound = Explode::new(ExplodeMode::Compound).on(Compare::Gt(4));
+        assert!(cmd.clone().explode(compound) == "2d6!!>4".parse().unwrap());
+        let penetrate = Explode::new(ExplodeMode::Penetrate);
+        let cmd = cmd.explode(penetrate).keep(Keep::Highest(1));
+        assert!(cmd == "2d6!pkh1".parse().unwrap());
+        assert!("2d6!!!".parse::<RollCommand>().is_err());
+        assert!("2d6!>".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn penetrating_dice_lose_one_per_explosion() {
+        let mut rng = [6, 6, 1].iter();
+        let cmd: RollCommand = "d6!p".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!("6+5+0 (11)" == result.to_string());
+    }
+
+    #[test]
+    fn explosion_depth_is_capped() {
+        let explode = Explode::new(ExplodeMode::Explode).depth(3);
+        let result = RollCommand::new(1, 6).explode(explode).result(&mut |max| max).unwrap();
+        assert!(24 == result.total());
+
+        let result = "d6!".parse::<RollCommand>().unwrap().result(&mut |max| max).unwrap();
+        assert!(6 * i128::from(DEFAULT_EXPLOSION_DEPTH + 1) == result.total());
+    }
+
+    #[test]
+    fn exploded_dice_are_kept_as_a_whole() {
+        let mut rng = [6, 2, 5].iter();
+        let cmd: RollCommand = "2d6!kh1".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!("6+2, ~~5~~ (8)" == result.to_string());
+    }
+
+    #[test]
+    fn can_parse_rerolls() {
+        let cmd = RollCommand::new(1, 20);
+        assert!(cmd.clone().reroll(Reroll::until(Compare::Eq(1))) == "d20r1".parse().unwrap());
+        assert!(cmd.clone().reroll(Reroll::until(Compare::Lt(3))) == "d20r<3".parse().unwrap());
+        assert!(cmd.reroll(Reroll::once(Compare::Le(2))) == "d20ro<=2".parse().unwrap());
+        assert!("d20r".parse::<RollCommand>().is_err());
+        assert!("d20r1ro2".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn reroll_once_keeps_the_second_face() {
+        let mut rng = [2, 1, 5].iter();
+        let cmd: RollCommand = "2d6ro<=2".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(result.values() == [1, 5]);
+        assert!("2r1, 5 (6)" == result.to_string());
+    }
+
+    #[test]
+    fn reroll_until_is_capped() {
+        let result = "d6r<7".parse::<RollCommand>().unwrap().result(&mut |max| max).unwrap();
+        let die = result.iter().next().unwrap();
+        assert!(DEFAULT_REROLL_DEPTH as usize == die.rerolled().len());
+    }
+
+    #[test]
+    fn rerolls_happen_before_explosions() {
+        let mut rng = [1, 6, 3].iter();
+        let cmd: RollCommand = "d6r1!".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!("1r6+3 (9)" == result.to_string());
+    }
+
+    #[test]
+    fn can_parse_fate_and_percentile_dice() {
+        assert!(RollCommand::with_faces(4, Faces::Fate) == "4dF".parse().unwrap());
+        assert!(RollCommand::new(1, 100) == "d%".parse().unwrap());
+        assert!(RollCommand::new(2, 100) == "2d%".parse().unwrap());
+        assert!("2dF6".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn fate_dice_can_total_below_zero() {
+        let result = "4dF".parse::<RollCommand>().unwrap().result(&mut |_| 1).unwrap();
+        assert!(-4 == result.total());
+        assert!("-, -, -, - (-4)" == result.to_string());
+    }
+
+    #[test]
+    fn can_parse_custom_faces() {
+        let faces = Faces::Custom(vec![1, 1, 2, 2, 3, 4]);
+        assert!(RollCommand::with_faces(3, faces) == "3d{1,1,2,2,3,4}".parse().unwrap());
+        let faces = Faces::Custom(vec![-1, 0, 1]);
+        assert!(RollCommand::with_faces(1, faces) == "d{-1,0,1}".parse().unwrap());
+        let faces = Faces::Symbols(vec!["hit".to_string(), "miss".to_string()]);
+        assert!(RollCommand::with_faces(2, faces) == "2d{hit,miss}".parse().unwrap());
+        assert!("d{}".parse::<RollCommand>().is_err());
+        assert!("d{1,hit}".parse::<RollCommand>().is_err());
+        assert!("d{1,2".parse::<RollCommand>().is_err());
+        assert!("d{1,,2}".parse::<RollCommand>().is_err());
+    }
+
+    #[test]
+    fn custom_numeric_faces_total() {
+        let mut rng = [1, 6, 4].iter();
+        let cmd: RollCommand = "3d{1,1,2,2,3,4}".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(result.values() == [1, 4, 2]);
+        assert!(7 == result.total());
+    }
+
+    #[test]
+    fn symbol_dice_are_tallied() {
+        let mut rng = [1, 3, 1].iter();
+        let cmd: RollCommand = "3d{hit,hit,miss,crit}".parse().unwrap();
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        assert!(0 == result.total());
+        assert!("hit, miss, hit (2 hit, 1 miss)" == result.to_string());
+    }
+
+    #[test]
+    fn percentile_dice_have_a_hundred_sides() {
+        let result = "d%".parse::<RollCommand>().unwrap().result(&mut |max| max).unwrap();
+        assert!(100 == result.total());
+    }
+
+    #[test]
+    fn keeping_more_than_rolled_keeps_everything() {
+        let result = RollCommand::new(2, 6).keep(Keep::Highest(3)).result(&mut |max| max).unwrap();
+        assert!(12 == result.total());
+    }
+
+    #[test]
+    fn dropping_more_than_rolled_drops_everything() {
+        let cmd = RollCommand::new(2, 6).keep(Keep::DropLowest(3));
+        let result = cmd.result(&mut |max| max).unwrap();
+        assert!(0 == result.total());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn results_serialize_with_totals() {
+        extern crate serde_json;
+
+        let mut rng = [5, 2].iter();
+        let cmd = RollCommand::new(2, 6).keep(Keep::Highest(1));
+        let result = cmd.result(&mut |_| *rng.next().unwrap()).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(serde_json::json!({"Numbered": 6}), json["faces"]);
+        assert_eq!(serde_json::json!(5), json["total"]);
+        assert_eq!(serde_json::json!(false), json["dice"][1]["kept"]);
+
+        let json = serde_json::to_value(&cmd).unwrap();
+        assert_eq!(serde_json::json!({"Highest": 1}), json["keep"]);
+        assert!(json.get("tally").is_none());
+
+        let cmd: RollCommand = "3d{hit,miss}".parse().unwrap();
+        let result = cmd.result(&mut SequenceSource::new(vec![2, 1, 2])).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(serde_json::json!([["hit", 1], ["miss", 2]]), json["tally"]);
+    }
+}
@@ -0,0 +1,266 @@
+//! Rolling an expression several times over, as in `6x4d6kh3`.
+
+use std::fmt;
+use std::slice;
+use std::str::FromStr;
+
+use error::{ParseError, RollError};
+use limits::{Budget, Limit, Limits};
+use parse;
+use source::DieSource;
+use {Expr, ExprResult};
+
+/// The order to sort the results of a `Repeat` in, by their totals.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// Rolls an expression a number of times, each independently of the
+/// others, such as a set of ability scores.
+///
+/// Written `6x4d6kh3` or `repeat(6, 4d6kh3)`. The repetition covers the
+/// whole expression after it, so `3x1d20 + 5` rolls `1d20 + 5` three times.
+/// A plain expression parses as a repeat rolled once. The repeated
+/// expression has to roll dice, so `2x6` is an error rather than `2x1d6`.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Order, Repeat};
+///
+/// let scores: Repeat = "6x4d6kh3".parse().unwrap();
+/// let scores = scores.sort(Order::Descending);
+/// let result = scores.result(&mut |max| max).unwrap();
+/// assert!(result.totals() == vec![18; 6]);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Repeat {
+    count: u32,
+    expr: Expr,
+    order: Option<Order>,
+}
+
+impl Repeat {
+    /// Constructs a repeat of `expr`, rolled `count` times in the order the
+    /// results come up.
+    pub fn new(count: u32, expr: Expr) -> Repeat {
+        Repeat { count, expr, order: None }
+    }
+
+    /// Returns this repeat with its results sorted by total in `order`.
+    pub fn sort(self, order: Order) -> Repeat {
+        Repeat { order: Some(order), ..self }
+    }
+
+    /// Parses a repeat like `str::parse`, but within the given limits
+    /// instead of the default ones.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<Repeat, ParseError> {
+        parse::repeat(s, limits)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn order(&self) -> Option<Order> {
+        self.order
+    }
+
+    /// Rolls the expression `count` times with `source`, within the
+    /// default `Limits`.
+    pub fn result<S>(&self, source: &mut S) -> Result<RepeatResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        self.result_with(source, &Limits::default())
+    }
+
+    /// Rolls the expression like `result`, but within the given limits
+    /// instead of the default ones.
+    ///
+    /// The limits cover all the repetitions together, as if the expression
+    /// had been written out `count` times.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Expr, Limit, Limits, Repeat, RollError};
+    ///
+    /// let expr: Expr = "10d6".parse().unwrap();
+    /// let limits = Limits::default().total_dice(50);
+    /// let err = Repeat::new(6, expr).result_with(&mut |max| max, &limits).err();
+    /// assert!(Some(RollError::LimitExceeded(Limit::TotalDice(50))) == err);
+    /// ```
+    pub fn result_with<S>(
+        &self,
+        source: &mut S,
+        limits: &Limits,
+    ) -> Result<RepeatResult, RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        if self.count == 0 {
+            return Err(RollError::ZeroCount);
+        }
+        if self.expr.nodes() > limits.nodes {
+            return Err(RollError::LimitExceeded(Limit::Nodes(limits.nodes)));
+        }
+        let mut budget = Budget::new(limits);
+        let mut results = Vec::new();
+        for _ in 0..self.count {
+            budget.step()?;
+            results.push(self.expr.eval(source, &mut budget)?);
+        }
+        match self.order {
+            Some(Order::Ascending) => results.sort_by_key(ExprResult::total),
+            Some(Order::Descending) => results.sort_by_key(|r| std::cmp::Reverse(r.total())),
+            None => {}
+        }
+        Ok(RepeatResult { results })
+    }
+}
+
+impl fmt::Display for Repeat {
+    /// Writes the repeat in dice notation: "6x4d6kh3", or just the
+    /// expression when it is rolled once.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.count {
+            1 => write!(f, "{}", self.expr),
+            count => write!(f, "{}x{}", count, self.expr),
+        }
+    }
+}
+
+/// Converts a string to a repeat, within the default `Limits`.
+///
+/// "6x4d6kh3" => Repeat {count: 6, expr: 4d6kh3}, "1d20" => Repeat {count:
+/// 1, expr: 1d20}, etc
+impl FromStr for Repeat {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Repeat, <Repeat as FromStr>::Err> {
+        parse::repeat(s, &Limits::default())
+    }
+}
+
+/// The results of a `Repeat`, in the order they came up unless the repeat
+/// sorts them. There is always at least one.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RepeatResult {
+    results: Vec<ExprResult>,
+}
+
+impl RepeatResult {
+    /// Returns an iterator over the results.
+    pub fn iter(&self) -> slice::Iter<'_, ExprResult> {
+        self.results.iter()
+    }
+
+    /// Returns the total of every result.
+    pub fn totals(&self) -> Vec<i128> {
+        self.results.iter().map(ExprResult::total).collect()
+    }
+
+    /// Returns the sum of all totals, saturating at the bounds of `i128`.
+    pub fn sum(&self) -> i128 {
+        self.results.iter().fold(0i128, |sum, r| sum.saturating_add(r.total()))
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.results.iter().map(|r| r.total() as f64).sum::<f64>() / self.results.len() as f64
+    }
+
+    pub fn lowest(&self) -> i128 {
+        self.results.iter().map(ExprResult::total).min().unwrap_or(0)
+    }
+
+    pub fn highest(&self) -> i128 {
+        self.results.iter().map(ExprResult::total).max().unwrap_or(0)
+    }
+
+    /// Sums up the results in one line: "sum 72, mean 12.00, lowest 8,
+    /// highest 16"
+    pub fn aggregate(&self) -> String {
+        format!(
+            "sum {}, mean {:.2}, lowest {}, highest {}",
+            self.sum(),
+            self.mean(),
+            self.lowest(),
+            self.highest()
+        )
+    }
+}
+
+impl fmt::Display for RepeatResult {
+    /// Writes every result on its own line, just like a single
+    /// `ExprResult` when there is only one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, result) in self.results.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", result)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod repeat_test {
+    use super::*;
+    use SequenceSource;
+
+    fn repeat(s: &str) -> Repeat {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn can_parse_repeats() {
+        assert_eq!(Repeat::new(6, "4d6kh3".parse().unwrap()), repeat("6x4d6kh3"));
+        assert_eq!(repeat("6x4d6kh3"), repeat("repeat(6, 4d6kh3)"));
+        assert_eq!(repeat("3x(1d20 + 5)"), repeat("3x1d20 + 5"));
+        assert_eq!("3x1d20 + 5", repeat("repeat( 3 , 1d20+5 )").to_string());
+        assert_eq!(1, repeat("2d6").count());
+
+        let malformed = [
+            "0x1d6", "2x6", "6x20", "repeat(6, 20)", "6 x 1d6", "6x", "x1d6", "repeat(6 1d6)",
+            "repeat(6, 1d6", "6x1d6)",
+        ];
+        for s in &malformed {
+            assert!(s.parse::<Repeat>().is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn repeats_count_towards_the_limits() {
+        let limits = Limits::default().total_dice(100);
+        assert!(Repeat::parse_with("10x10d6", &limits).is_ok());
+        let err = Repeat::parse_with("11x10d6", &limits).unwrap_err();
+        assert_eq!(ParseError::LimitExceeded { limit: Limit::TotalDice(100), span: 0..2 }, err);
+
+        let limits = Limits::default().steps(10);
+        let err = Repeat::new(20, Expr::Constant(1)).result_with(&mut |max| max, &limits).err();
+        assert_eq!(Some(RollError::LimitExceeded(Limit::Steps(10))), err);
+    }
+
+    #[test]
+    fn results_can_be_sorted_and_aggregated() {
+        let roll = |repeat: Repeat| {
+            let mut source = SequenceSource::new(vec![3, 1, 4, 1, 5]);
+            repeat.result(&mut source).unwrap()
+        };
+        let result = roll(repeat("5x1d6"));
+        assert_eq!(vec![3, 1, 4, 1, 5], result.totals());
+        assert_eq!("3 (3)\n1 (1)\n4 (4)\n1 (1)\n5 (5)", result.to_string());
+        assert_eq!("sum 14, mean 2.80, lowest 1, highest 5", result.aggregate());
+
+        assert_eq!(vec![1, 1, 3, 4, 5], roll(repeat("5x1d6").sort(Order::Ascending)).totals());
+        assert_eq!(vec![5, 4, 3, 1, 1], roll(repeat("5x1d6").sort(Order::Descending)).totals());
+    }
+}
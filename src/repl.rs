@@ -0,0 +1,235 @@
+//! The interactive mode of `dice`, rolling the expression on every line.
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+use rcmd::{DieSource, OsSource, Order, Repeat, RollError, SeededSource};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HELP: &str = "\
+type a roll expression to roll it, like 4d6kh3, 1d20 + 5 or 6x4d6kh3, or a command:
+  :help      print this help
+  :seed      print the seed of the rolls
+  :seed <n>  roll with a generator seeded with n from now on
+  :stats     print statistics of the totals rolled so far
+  :quit      leave, as does ctrl-d";
+
+/// Where the history is kept between sessions, `~/.dice_history`.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".dice_history"))
+}
+
+/// What to show for a line.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Reply {
+    Print(String),
+    Error(String),
+    Nothing,
+    Quit,
+}
+
+/// The totals rolled in a session.
+#[derive(Default)]
+struct Stats {
+    rolls: u64,
+    failures: u64,
+    sum: i128,
+    lowest: Option<i128>,
+    highest: Option<i128>,
+}
+
+impl Stats {
+    fn add(&mut self, total: i128) {
+        self.rolls += 1;
+        self.sum = self.sum.saturating_add(total);
+        self.lowest = Some(self.lowest.map_or(total, |lowest| lowest.min(total)));
+        self.highest = Some(self.highest.map_or(total, |highest| highest.max(total)));
+    }
+}
+
+/// The state kept from one line to the next: the source of the rolls, and
+/// statistics of what was rolled.
+pub struct Session {
+    source: Box<dyn DieSource>,
+    seed: Option<u64>,
+    order: Option<Order>,
+    aggregate: bool,
+    stats: Stats,
+}
+
+impl Session {
+    /// Starts a session rolling with a generator seeded with `seed`, or with
+    /// the os randomness if there is no seed.
+    pub fn new(seed: Option<u64>) -> io::Result<Session> {
+        let source: Box<dyn DieSource> = match seed {
+            Some(seed) => Box::new(SeededSource::new(seed)),
+            None => Box::new(OsSource::new()?),
+        };
+        Ok(Session { source, seed, order: None, aggregate: false, stats: Stats::default() })
+    }
+
+    /// Returns this session with the results of repeats sorted in `order`,
+    /// if given.
+    pub fn sort(self, order: Option<Order>) -> Session {
+        Session { order, ..self }
+    }
+
+    /// Returns this session printing the aggregate of the results after
+    /// every repeat if `aggregate` is set.
+    pub fn aggregate(self, aggregate: bool) -> Session {
+        Session { aggregate, ..self }
+    }
+
+    /// Rolls the expression on `line`, or runs the command on it.
+    pub fn eval(&mut self, line: &str) -> Reply {
+        let line = line.trim();
+        if line.is_empty() {
+            return Reply::Nothing;
+        }
+        if !line.starts_with(':') {
+            let reply = match line.parse::<Repeat>() {
+                Ok(repeat) => match self.roll(repeat) {
+                    Ok(reply) => return Reply::Print(reply),
+                    Err(e) => format!("{}: {}", line, e),
+                },
+                Err(e) => e.render(line),
+            };
+            self.stats.failures += 1;
+            return Reply::Error(reply);
+        }
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some(":help"), None, _) => Reply::Print(HELP.to_string()),
+            (Some(":quit"), None, _) | (Some(":q"), None, _) => Reply::Quit,
+            (Some(":seed"), None, _) => Reply::Print(match self.seed {
+                Some(seed) => format!("seed: {}", seed),
+                None => "rolling with the os randomness".to_string(),
+            }),
+            (Some(":seed"), Some(seed), None) => match seed.parse() {
+                Ok(seed) => {
+                    self.source = Box::new(SeededSource::new(seed));
+                    self.seed = Some(seed);
+                    Reply::Print(format!("seeded with {}", seed))
+                }
+                Err(_) => Reply::Error(format!(
+                    ":seed needs a number between 0 and {}, not '{}'",
+                    u64::MAX,
+                    seed
+                )),
+            },
+            (Some(":stats"), None, _) => Reply::Print(self.stats()),
+            (Some(command), ..) => {
+                Reply::Error(format!("unknown command '{}', try :help", command))
+            }
+            (None, ..) => Reply::Nothing,
+        }
+    }
+
+    /// Rolls `repeat`, adding every total to the statistics.
+    fn roll(&mut self, repeat: Repeat) -> Result<String, RollError> {
+        let count = repeat.count();
+        let repeat = match self.order {
+            Some(order) => repeat.sort(order),
+            None => repeat,
+        };
+        let results = repeat.result(&mut *self.source)?;
+        for total in results.totals() {
+            self.stats.add(total);
+        }
+        let mut s = results.to_string();
+        if self.aggregate && count > 1 {
+            s += &format!("\n{}", results.aggregate());
+        }
+        Ok(s)
+    }
+
+    fn stats(&self) -> String {
+        let stats = &self.stats;
+        let mut s = format!("rolls: {}, failed: {}", stats.rolls, stats.failures);
+        if let (Some(lowest), Some(highest)) = (stats.lowest, stats.highest) {
+            let mean = stats.sum as f64 / stats.rolls as f64;
+            s += &format!("\ntotals: mean {:.2}, lowest {}, highest {}", mean, lowest, highest);
+        }
+        s
+    }
+}
+
+/// Reads lines from the terminal until `:quit` or the end of input, with
+/// the history of earlier sessions.
+pub fn run(mut session: Session) -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(ref path) = history {
+        // there is no history on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline("dice> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        match session.eval(&line) {
+            Reply::Print(s) => println!("{}", s),
+            Reply::Error(s) => eprintln!("{}", s),
+            Reply::Nothing => {}
+            Reply::Quit => break,
+        }
+    }
+
+    if let Some(ref path) = history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod repl_test {
+    use super::*;
+
+    #[test]
+    fn rolls_expressions() {
+        let mut session = Session::new(Some(3)).unwrap();
+        let first = session.eval("4d6kh3 + 2");
+        assert!(matches!(first, Reply::Print(_)));
+        assert_eq!(Reply::Print("seeded with 3".to_string()), session.eval("  :seed 3 "));
+        assert_eq!(first, session.eval("4d6kh3 + 2"));
+        assert_eq!(Reply::Print("seed: 3".to_string()), session.eval(":seed"));
+        assert_eq!(Reply::Nothing, session.eval("   "));
+        assert!(matches!(session.eval("2d"), Reply::Error(_)));
+    }
+
+    #[test]
+    fn runs_commands() {
+        let mut session = Session::new(Some(1)).unwrap();
+        assert_eq!(Reply::Quit, session.eval(":quit"));
+        assert!(matches!(session.eval(":help"), Reply::Print(_)));
+        assert!(matches!(session.eval(":seed x"), Reply::Error(_)));
+        assert!(matches!(session.eval(":roll"), Reply::Error(_)));
+
+        assert_eq!(Reply::Print("rolls: 0, failed: 0".to_string()), session.eval(":stats"));
+        session.eval("1d1 + 2");
+        session.eval("1d1 + 4");
+        session.eval("2d");
+        let stats = "rolls: 2, failed: 1\ntotals: mean 4.00, lowest 3, highest 5";
+        assert_eq!(Reply::Print(stats.to_string()), session.eval(":stats"));
+    }
+
+    #[test]
+    fn rolls_repeats() {
+        let mut session = Session::new(Some(1)).unwrap().aggregate(true);
+        let rolled = "[1] + 2 (3)\n[1] + 2 (3)\nsum 6, mean 3.00, lowest 3, highest 3";
+        assert_eq!(Reply::Print(rolled.to_string()), session.eval("2x1d1 + 2"));
+        assert_eq!(Reply::Print("1 (1)".to_string()), session.eval("repeat(1, 1d1)"));
+        let stats = "rolls: 3, failed: 0\ntotals: mean 2.33, lowest 1, highest 3";
+        assert_eq!(Reply::Print(stats.to_string()), session.eval(":stats"));
+    }
+}
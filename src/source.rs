@@ -0,0 +1,364 @@
+//! Sources of randomness for rolling dice.
+
+use std::io;
+
+use rand::chacha::ChaChaRng;
+use rand::{OsRng, Rng, SeedableRng};
+
+use error::RollError;
+
+/// Picks the side a die lands on.
+///
+/// Every roll asks its source for one side at a time, passing the number
+/// of sides of the die. A source must return a side between 1 and `sides`;
+/// anything else makes the roll fail.
+///
+/// Any `FnMut(u32) -> u32` is a source, which makes it easy to roll with
+/// fixed values:
+///
+/// ```
+/// use rcmd::RollCommand;
+///
+/// let result = RollCommand::new(2, 6).result(&mut |max| max).unwrap();
+/// assert!(12 == result.total());
+/// ```
+pub trait DieSource {
+    /// Picks a side of a die with `sides` sides.
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError>;
+}
+
+impl<F: FnMut(u32) -> u32> DieSource for F {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        Ok(self(sides))
+    }
+}
+
+/// Picks a side between 1 and `sides` from raw random words, with every
+/// side equally likely.
+///
+/// Taking a word modulo `sides` favours the low sides whenever `sides` does
+/// not divide 2^32. Instead, words from the incomplete block of `sides` at
+/// the top of the range are rejected and `next_word` is asked again, which
+/// happens for less than half of all words whatever `sides` is.
+///
+/// # Panics
+/// Panics if `sides` is 0.
+///
+/// # Examples
+/// ```
+/// use rcmd::sample_side;
+///
+/// // u32::MAX falls into the incomplete block of 3 and is rejected
+/// let mut words = vec![5, u32::MAX].into_iter().rev();
+/// assert!(3 == sample_side(3, &mut || words.next().unwrap()));
+/// ```
+pub fn sample_side<W: FnMut() -> u32>(sides: u32, next_word: &mut W) -> u32 {
+    assert!(sides > 0, "cannot pick a side of a die without sides");
+    let sides = u64::from(sides);
+    let words = u64::from(u32::MAX) + 1;
+    let zone = words - words % sides;
+    loop {
+        let word = u64::from(next_word());
+        if word < zone {
+            return (word % sides) as u32 + 1;
+        }
+    }
+}
+
+/// Rolls with any source of raw random words, picking sides uniformly with
+/// `sample_side`.
+///
+/// # Examples
+/// ```
+/// extern crate rand;
+/// extern crate rcmd;
+///
+/// use rand::{Rng, XorShiftRng};
+/// use rcmd::{RollCommand, WordSource};
+///
+/// # fn main() {
+/// let mut rng = XorShiftRng::new_unseeded();
+/// let mut source = WordSource::new(|| rng.next_u32());
+/// let result = RollCommand::new(3, 6).result(&mut source).unwrap();
+/// assert!(result.values().iter().all(|&n| n >= 1 && n <= 6));
+/// # }
+/// ```
+pub struct WordSource<W> {
+    next_word: W,
+}
+
+impl<W: FnMut() -> u32> WordSource<W> {
+    pub fn new(next_word: W) -> WordSource<W> {
+        WordSource { next_word }
+    }
+}
+
+impl<W: FnMut() -> u32> DieSource for WordSource<W> {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        Ok(sample_side(sides, &mut self.next_word))
+    }
+}
+
+/// Rolls with randomness from the operating system.
+pub struct OsSource {
+    rng: OsRng,
+}
+
+impl OsSource {
+    /// Constructs a new OsSource, failing if the operating system does not
+    /// provide any randomness.
+    pub fn new() -> io::Result<OsSource> {
+        Ok(OsSource { rng: OsRng::new()? })
+    }
+}
+
+impl DieSource for OsSource {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        let rng = &mut self.rng;
+        Ok(sample_side(sides, &mut || rng.next_u32()))
+    }
+}
+
+/// Rolls with a ChaCha random number generator started from a seed.
+///
+/// The same seed always gives the same sides, on every platform, so a
+/// whole session of rolls can be reproduced from its seed.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Expr, SeededSource};
+///
+/// let expr: Expr = "4d6kh3 + 2".parse().unwrap();
+/// let first = expr.result(&mut SeededSource::new(42)).unwrap();
+/// let again = expr.result(&mut SeededSource::new(42)).unwrap();
+/// assert!(first.to_string() == again.to_string());
+/// ```
+pub struct SeededSource {
+    rng: ChaChaRng,
+}
+
+impl SeededSource {
+    pub fn new(seed: u64) -> SeededSource {
+        let seed = [seed as u32, (seed >> 32) as u32];
+        SeededSource { rng: ChaChaRng::from_seed(&seed) }
+    }
+}
+
+impl DieSource for SeededSource {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        let rng = &mut self.rng;
+        Ok(sample_side(sides, &mut || rng.next_u32()))
+    }
+}
+
+/// Rolls a fixed sequence of sides, in order, whatever the dice.
+///
+/// Fails with `RollError::Exhausted` once the sequence runs out.
+///
+/// # Examples
+/// ```
+/// use rcmd::{RollCommand, RollError, SequenceSource};
+///
+/// let mut source = SequenceSource::new(vec![3, 5]);
+/// let result = RollCommand::new(2, 6).result(&mut source).unwrap();
+/// assert!(8 == result.total());
+///
+/// let err = RollCommand::new(1, 6).result(&mut source).err();
+/// assert!(Some(RollError::Exhausted) == err);
+/// ```
+pub struct SequenceSource {
+    sides: Vec<u32>,
+    next: usize,
+}
+
+impl SequenceSource {
+    pub fn new(sides: Vec<u32>) -> SequenceSource {
+        SequenceSource { sides, next: 0 }
+    }
+}
+
+impl DieSource for SequenceSource {
+    fn pick(&mut self, _: u32) -> Result<u32, RollError> {
+        let side = *self.sides.get(self.next).ok_or(RollError::Exhausted)?;
+        self.next += 1;
+        Ok(side)
+    }
+}
+
+/// Wraps another source and records every side it picks, along with the
+/// number of sides of the die it was picked for.
+///
+/// # Examples
+/// ```
+/// use rcmd::{Expr, Recorder, SeededSource};
+///
+/// let expr: Expr = "2d20kh1 + 1d4".parse().unwrap();
+/// let mut recorder = Recorder::new(SeededSource::new(7));
+/// let rolled = expr.result(&mut recorder).unwrap();
+///
+/// let mut replay = recorder.replay();
+/// let replayed = expr.result(&mut replay).unwrap();
+/// assert!(rolled.to_string() == replayed.to_string());
+/// ```
+pub struct Recorder<S> {
+    source: S,
+    picks: Vec<(u32, u32)>,
+}
+
+impl<S: DieSource> Recorder<S> {
+    pub fn new(source: S) -> Recorder<S> {
+        Recorder { source, picks: Vec::new() }
+    }
+
+    /// Returns the picks recorded so far as pairs of sides and the side
+    /// that was picked.
+    pub fn picks(&self) -> &[(u32, u32)] {
+        &self.picks
+    }
+
+    /// Returns a source replaying the picks recorded so far.
+    pub fn replay(&self) -> Replay {
+        Replay::new(self.picks.clone())
+    }
+}
+
+impl<S: DieSource> DieSource for Recorder<S> {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        let side = self.source.pick(sides)?;
+        self.picks.push((sides, side));
+        Ok(side)
+    }
+}
+
+/// Replays picks recorded by a `Recorder`.
+///
+/// Replaying checks that every die asks for the same number of sides as it
+/// did when it was recorded, and fails with `RollError::Diverged` as soon
+/// as one does not, so a replay cannot silently roll something else.
+pub struct Replay {
+    picks: Vec<(u32, u32)>,
+    next: usize,
+}
+
+impl Replay {
+    /// Constructs a Replay from pairs of sides and the side that was picked.
+    pub fn new(picks: Vec<(u32, u32)>) -> Replay {
+        Replay { picks, next: 0 }
+    }
+
+    /// Returns the number of recorded picks replayed so far.
+    pub fn used(&self) -> usize {
+        self.next
+    }
+
+    /// Returns true once every recorded pick has been replayed.
+    pub fn is_done(&self) -> bool {
+        self.next == self.picks.len()
+    }
+}
+
+impl DieSource for Replay {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        let (recorded, side) = *self.picks.get(self.next).ok_or(RollError::Exhausted)?;
+        if recorded != sides {
+            return Err(RollError::Diverged { pick: self.next, recorded, sides });
+        }
+        self.next += 1;
+        Ok(side)
+    }
+}
+
+#[cfg(test)]
+mod source_test {
+    use super::*;
+    use rand::XorShiftRng;
+    use RollCommand;
+
+    /// Returns the chi-square statistic of `counts` against a uniform
+    /// distribution.
+    fn chi_square(counts: &[u64]) -> f64 {
+        let total: u64 = counts.iter().sum();
+        let expected = total as f64 / counts.len() as f64;
+        counts.iter().map(|&n| (n as f64 - expected).powi(2) / expected).sum()
+    }
+
+    /// Returns the critical value of the chi-square distribution with `df`
+    /// degrees of freedom at a significance of about one in a million,
+    /// using the Wilson-Hilferty approximation.
+    fn critical(df: usize) -> f64 {
+        const Z: f64 = 4.753;
+        let k = 2.0 / (9.0 * df as f64);
+        df as f64 * (1.0 - k + Z * k.sqrt()).powi(3)
+    }
+
+    /// Rolls a die with `sides` sides `per_side` times as often as it has
+    /// sides, and checks that the sides come up uniformly.
+    fn is_uniform<F: FnMut(u32) -> u32>(sides: u32, per_side: u64, mut pick: F) -> bool {
+        let mut counts = vec![0; sides as usize];
+        for _ in 0..u64::from(sides) * per_side {
+            counts[pick(sides) as usize - 1] += 1;
+        }
+        chi_square(&counts) < critical(sides as usize - 1)
+    }
+
+    #[test]
+    fn sampled_sides_are_uniform() {
+        let mut rng = XorShiftRng::new_unseeded();
+        for sides in 2..1001 {
+            let mut next_word = || rng.next_u32();
+            let uniform = is_uniform(sides, 20, |sides| sample_side(sides, &mut next_word));
+            assert!(uniform, "d{} is not uniform", sides);
+        }
+    }
+
+    #[test]
+    fn seeded_sides_are_uniform() {
+        let mut source = SeededSource::new(14);
+        for &sides in &[2, 3, 6, 10, 20, 100, 1000] {
+            assert!(is_uniform(sides, 50, |sides| source.pick(sides).unwrap()));
+        }
+    }
+
+    #[test]
+    fn harness_detects_modulo_bias() {
+        // a byte modulo 100 favours the sides up to 56
+        let mut rng = XorShiftRng::new_unseeded();
+        assert!(!is_uniform(100, 50, |sides| (rng.next_u32() & 0xff) % sides + 1));
+    }
+
+    #[test]
+    fn sample_side_handles_the_full_range() {
+        let mut words = vec![7, u32::MAX - 1, u32::MAX].into_iter().rev();
+        assert_eq!(u32::MAX, sample_side(u32::MAX, &mut || words.next().unwrap()));
+        // nothing is rejected when the sides divide 2^32
+        let mut words = vec![u32::MAX].into_iter();
+        assert_eq!(1 << 31, sample_side(1 << 31, &mut || words.next().unwrap()));
+    }
+
+    #[test]
+    fn seeded_sources_are_deterministic() {
+        let picks = |seed| {
+            let mut source = SeededSource::new(seed);
+            (0..100).map(|_| source.pick(20).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(1), picks(1));
+        assert!(picks(1) != picks(2));
+        assert!(picks(1).iter().all(|side| (1..=20).contains(side)));
+    }
+
+    #[test]
+    fn replays_detect_divergence() {
+        let mut recorder = Recorder::new(SeededSource::new(3));
+        RollCommand::new(2, 6).result(&mut recorder).unwrap();
+        assert_eq!(2, recorder.picks().len());
+
+        let mut replay = recorder.replay();
+        let err = RollCommand::new(2, 8).result(&mut replay).err();
+        assert_eq!(Some(RollError::Diverged { pick: 0, recorded: 6, sides: 8 }), err);
+
+        let mut replay = recorder.replay();
+        RollCommand::new(2, 6).result(&mut replay).unwrap();
+        assert!(replay.is_done());
+        assert_eq!(Some(RollError::Exhausted), RollCommand::new(1, 6).result(&mut replay).err());
+    }
+}
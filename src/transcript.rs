@@ -0,0 +1,250 @@
+//! Transcripts of rolls that can be stored and replayed.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use error::{ReplayError, RollError};
+use source::{DieSource, Replay};
+use {Expr, ExprResult};
+
+/// A record of one roll: the expression, the seed it was rolled with, if
+/// any, when it was rolled and every side picked along the way.
+///
+/// Replaying the log rolls the expression again with the recorded sides,
+/// which reproduces the exact result, or fails if the expression or the
+/// sides were tampered with.
+///
+/// A log is written as one `key: value` line per field, and can be parsed
+/// back from that form:
+///
+/// ```text
+/// expression: 2d20kh1 + 5
+/// seed: 42
+/// timestamp: 1700000000
+/// picks: 20:7 20:16
+/// ```
+///
+/// # Examples
+/// ```
+/// use rcmd::{Expr, RollLog, SeededSource};
+///
+/// let expr: Expr = "2d20kh1 + 5".parse().unwrap();
+/// let mut source = SeededSource::new(42);
+/// let (result, log) = RollLog::record(&expr, &mut source, Some(42)).unwrap();
+///
+/// let log: RollLog = log.to_string().parse().unwrap();
+/// assert!(log.replay().unwrap().to_string() == result.to_string());
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RollLog {
+    expression: String,
+    seed: Option<u64>,
+    timestamp: u64,
+    picks: Vec<(u32, u32)>,
+}
+
+/// Records the sides picked by a borrowed source.
+struct Recording<'a, S: DieSource + ?Sized + 'a> {
+    source: &'a mut S,
+    picks: Vec<(u32, u32)>,
+}
+
+impl<'a, S: DieSource + ?Sized> DieSource for Recording<'a, S> {
+    fn pick(&mut self, sides: u32) -> Result<u32, RollError> {
+        let side = self.source.pick(sides)?;
+        self.picks.push((sides, side));
+        Ok(side)
+    }
+}
+
+impl RollLog {
+    /// Rolls `expr` with `source`, recording every side picked.
+    ///
+    /// `seed` is only kept for reference, pass the seed `source` was
+    /// created from if there is one. The timestamp is taken from the
+    /// system clock.
+    pub fn record<S>(
+        expr: &Expr,
+        source: &mut S,
+        seed: Option<u64>,
+    ) -> Result<(ExprResult, RollLog), RollError>
+    where
+        S: DieSource + ?Sized,
+    {
+        let mut recording = Recording { source, picks: Vec::new() };
+        let result = expr.result(&mut recording)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let expression = expr.to_string();
+        Ok((result, RollLog { expression, seed, timestamp, picks: recording.picks }))
+    }
+
+    /// Returns the expression that was rolled, in canonical notation.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Returns the seed the roll was made with, if it was recorded.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns when the roll was made, in seconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Returns the sides picked, as pairs of the sides of the die and the
+    /// side it landed on.
+    pub fn picks(&self) -> &[(u32, u32)] {
+        &self.picks
+    }
+
+    /// Rolls the recorded expression again with the recorded sides.
+    pub fn replay(&self) -> Result<ExprResult, ReplayError> {
+        let expr: Expr = self.expression.parse().map_err(ReplayError::Parse)?;
+        self.verify(&expr)
+    }
+
+    /// Rolls `expr` with the recorded sides, checking that it is the
+    /// expression that was recorded.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Expr, ReplayError, RollLog};
+    ///
+    /// let expr: Expr = "1d20 + 5".parse().unwrap();
+    /// let (_, log) = RollLog::record(&expr, &mut |_| 11, None).unwrap();
+    ///
+    /// let altered: Expr = "1d20 + 7".parse().unwrap();
+    /// let err = log.verify(&altered).err();
+    /// assert!(matches!(err, Some(ReplayError::ExpressionChanged { .. })));
+    /// ```
+    pub fn verify(&self, expr: &Expr) -> Result<ExprResult, ReplayError> {
+        let found = expr.to_string();
+        if found != self.expression {
+            let recorded = self.expression.clone();
+            return Err(ReplayError::ExpressionChanged { recorded, found });
+        }
+
+        let mut replay = Replay::new(self.picks.clone());
+        let result = expr.result(&mut replay).map_err(ReplayError::Roll)?;
+        if !replay.is_done() {
+            let recorded = self.picks.len();
+            return Err(ReplayError::UnusedPicks { used: replay.used(), recorded });
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for RollLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "expression: {}", self.expression)?;
+        match self.seed {
+            Some(seed) => writeln!(f, "seed: {}", seed)?,
+            None => writeln!(f, "seed: none")?,
+        }
+        writeln!(f, "timestamp: {}", self.timestamp)?;
+        let picks: Vec<_> = self.picks.iter().map(|&(s, n)| format!("{}:{}", s, n)).collect();
+        writeln!(f, "picks: {}", picks.join(" "))
+    }
+}
+
+/// Reads a log back from the form it is displayed in.
+impl FromStr for RollLog {
+    type Err = ReplayError;
+
+    fn from_str(s: &str) -> Result<RollLog, ReplayError> {
+        let mut lines = s.lines().enumerate();
+        let mut field = |key: &str| {
+            let (i, line) = lines.next().ok_or(ReplayError::Malformed { line: 0 })?;
+            let malformed = ReplayError::Malformed { line: i + 1 };
+            match line.split_once(": ").or_else(|| line.split_once(':')) {
+                Some((k, value)) if k == key => Ok((value.trim().to_string(), malformed)),
+                _ => Err(malformed),
+            }
+        };
+
+        let (expression, _) = field("expression")?;
+        let seed = match field("seed")? {
+            (ref value, _) if value == "none" => None,
+            (value, malformed) => Some(value.parse().map_err(|_| malformed)?),
+        };
+        let (timestamp, malformed) = field("timestamp")?;
+        let timestamp = timestamp.parse().map_err(|_| malformed)?;
+        let (value, malformed) = field("picks")?;
+        let mut picks = Vec::new();
+        for pick in value.split_whitespace() {
+            let (sides, side) = pick.split_once(':').ok_or_else(|| malformed.clone())?;
+            match (sides.parse(), side.parse()) {
+                (Ok(sides), Ok(side)) => picks.push((sides, side)),
+                _ => return Err(malformed),
+            }
+        }
+        Ok(RollLog { expression, seed, timestamp, picks })
+    }
+}
+
+#[cfg(test)]
+mod transcript_test {
+    use super::*;
+    use {SeededSource, SequenceSource};
+
+    fn record(s: &str) -> (ExprResult, RollLog) {
+        let expr: Expr = s.parse().unwrap();
+        RollLog::record(&expr, &mut SeededSource::new(15), Some(15)).unwrap()
+    }
+
+    #[test]
+    fn replays_reproduce_the_result() {
+        let (result, log) = record("4d6kh3 + 1d8!");
+        assert_eq!(Some(15), log.seed());
+        assert!(log.picks().len() >= 5);
+        assert_eq!(result.to_string(), log.replay().unwrap().to_string());
+    }
+
+    #[test]
+    fn replays_exploding_pools_with_rerolls() {
+        let expr: Expr = "8d10!r1>=7".parse().unwrap();
+        let mut source = SequenceSource::new(vec![10, 4, 1, 7, 10, 10, 2, 3, 8, 6, 9, 5]);
+        let (result, log) = RollLog::record(&expr, &mut source, None).unwrap();
+        assert_eq!("8d10r1!=10>=7", log.expression());
+        assert_eq!(12, log.picks().len());
+        assert_eq!(result.to_string(), log.replay().unwrap().to_string());
+    }
+
+    #[test]
+    fn logs_survive_display() {
+        let (_, log) = record("3d{hit,miss} + d6r1");
+        assert_eq!(log, log.to_string().parse().unwrap());
+
+        let log = RollLog { expression: "1d6".into(), seed: None, timestamp: 7, picks: vec![] };
+        assert_eq!("expression: 1d6\nseed: none\ntimestamp: 7\npicks: \n", log.to_string());
+        assert_eq!(log, log.to_string().parse().unwrap());
+        let err = "expression: 1d6\nseed: 1\nstamp: 7".parse::<RollLog>().unwrap_err();
+        assert_eq!(ReplayError::Malformed { line: 3 }, err);
+    }
+
+    #[test]
+    fn replays_detect_tampering() {
+        let (_, log) = record("2d20kh1 + 5");
+
+        let mut tampered = log.clone();
+        tampered.expression = "2d12kh1 + 5".to_string();
+        let err = RollError::Diverged { pick: 0, recorded: 20, sides: 12 };
+        assert_eq!(Err(ReplayError::Roll(err)), tampered.replay().map(|r| r.total()));
+
+        let mut tampered = log.clone();
+        tampered.picks.push((20, 20));
+        let err = ReplayError::UnusedPicks { used: 2, recorded: 3 };
+        assert_eq!(Err(err), tampered.replay().map(|r| r.total()));
+
+        let mut tampered = log;
+        tampered.picks.pop();
+        let err = ReplayError::Roll(RollError::Exhausted);
+        assert_eq!(Err(err), tampered.replay().map(|r| r.total()));
+    }
+}